@@ -0,0 +1,106 @@
+#[macro_use]
+extern crate criterion;
+extern crate serde_mcf;
+extern crate serde_derive;
+
+use criterion::{black_box, Criterion};
+
+use serde_mcf::legacy::{BcryptHash, Pbkdf2Hash, ShaCryptHash};
+use serde_mcf::{from_str, to_string, McfHash};
+
+const ARGON2: &str = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                      $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+const BCRYPT: &str = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+const PBKDF2: &str = "$pbkdf2-sha256$29000$9t7be09prfXee2/NOUeotQ\
+                      $yIPphVdF97u.XzygN1ToroW.pJoenSO2MPWQIaDuH7Y";
+const SHA512CRYPT: &str = "$6$rounds=5000$toolongsaltstrin$lQ8jolhgVRVhY4b5pZKaysCLi0QBxGoNeKQzQ3\
+                           glMhwllF7oGDZxUhx1yxdYcz/e1JSbq3y6JMxxl8audkUEm0";
+
+fn bench_from_str(c: &mut Criterion) {
+    c.bench_function("from_str argon2", |b| b.iter(|| from_str::<McfHash>(black_box(ARGON2))));
+    c.bench_function("from_str bcrypt", |b| {
+        b.iter(|| from_str::<BcryptHash>(black_box(BCRYPT)))
+    });
+    c.bench_function("from_str pbkdf2", |b| {
+        b.iter(|| from_str::<Pbkdf2Hash>(black_box(PBKDF2)))
+    });
+    c.bench_function("from_str sha512crypt", |b| {
+        b.iter(|| from_str::<ShaCryptHash>(black_box(SHA512CRYPT)))
+    });
+}
+
+fn bench_large_params(c: &mut Criterion) {
+    let params = (0..200).map(|i| format!("p{}={}", i, i)).collect::<Vec<_>>().join(",");
+    let input = format!("$argon2i${}$c29tZXNhbHQ$aGFzaA", params);
+    c.bench_function("from_str 200 params", |b| {
+        b.iter(|| from_str::<McfHash>(black_box(&input)))
+    });
+}
+
+fn bench_serialize_large_params(c: &mut Criterion) {
+    let params = (0..200).map(|i| format!("p{}={}", i, i)).collect::<Vec<_>>().join(",");
+    let hash: McfHash = from_str(&format!("$argon2i${}$c29tZXNhbHQ$aGFzaA", params)).unwrap();
+    c.bench_function("to_string 200 params", |b| b.iter(|| to_string(black_box(&hash))));
+}
+
+fn bench_parse_into(c: &mut Criterion) {
+    c.bench_function("from_str argon2 x100", |b| {
+        b.iter(|| for _ in 0..100 {
+            black_box(from_str::<McfHash>(black_box(ARGON2)).unwrap());
+        })
+    });
+    let mut hash: McfHash = from_str(ARGON2).unwrap();
+    c.bench_function("parse_into argon2 x100", |b| {
+        b.iter(|| for _ in 0..100 {
+            hash.parse_into(black_box(ARGON2)).unwrap();
+        })
+    });
+}
+
+// Only derived structs built with `--features deserialize_in_place` reuse
+// their field buffers. Without it, `from_str_into` builds a new struct just
+// as `from_str` does, so there is nothing to compare.
+#[cfg(feature = "deserialize_in_place")]
+fn bench_from_str_into(c: &mut Criterion) {
+    use serde_derive::Deserialize;
+    use serde_mcf::from_str_into;
+
+    #[allow(dead_code)]
+    #[derive(Default, Deserialize)]
+    struct Fields {
+        algorithm: String,
+        params: String,
+        salt: String,
+        hash: String,
+    }
+
+    c.bench_function("from_str fields x100", |b| {
+        b.iter(|| for _ in 0..100 {
+            black_box(from_str::<Fields>(black_box(ARGON2)).unwrap());
+        })
+    });
+    let mut fields = Fields::default();
+    c.bench_function("from_str_into fields x100", |b| {
+        b.iter(|| for _ in 0..100 {
+            from_str_into(black_box(ARGON2), &mut fields).unwrap();
+        })
+    });
+}
+
+#[cfg(not(feature = "deserialize_in_place"))]
+fn bench_from_str_into(_: &mut Criterion) {}
+
+fn bench_round_trip(c: &mut Criterion) {
+    c.bench_function("round trip argon2", |b| {
+        b.iter(|| to_string(&from_str::<McfHash>(black_box(ARGON2)).unwrap()))
+    });
+}
+
+criterion_group!(benches,
+                 bench_from_str,
+                 bench_large_params,
+                 bench_serialize_large_params,
+                 bench_parse_into,
+                 bench_from_str_into,
+                 bench_round_trip);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate serde_mcf;
+
+use serde_mcf::{from_str, legacy, McfHash};
+
+fuzz_target!(|data: &[u8]| {
+    let input = String::from_utf8_lossy(data);
+    let _ = from_str::<McfHash>(&input);
+    let _ = from_str::<legacy::BcryptHash>(&input);
+});
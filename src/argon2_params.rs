@@ -0,0 +1,143 @@
+//! Helper methods to serialize the version and parameters of an argon2 hash
+//! as a single `Argon2Params` value.
+//!
+//! Argon2 hashes have the version and the cost parameters in separate
+//! fields, as in `$argon2i$v=19$m=65536,t=2,p=1$salt$hash`. Older hashes
+//! have no version field. Use this module with `#[serde(with =
+//! "argon2_params")]` on an `Argon2Params` field in place of both.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use prelude::*;
+
+use serde::{Deserializer, Serialize, Serializer};
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::ser::SerializeTupleStruct;
+
+/// The version and cost parameters of an argon2 hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// The `v` parameter, or `None` for hashes from before it was added.
+    pub version: Option<u32>,
+    /// Memory cost, in KiB.
+    pub m: u32,
+    /// Number of iterations.
+    pub t: u32,
+    /// Degree of parallelism.
+    pub p: u32,
+}
+
+const FIELDS: &[&str] = &["v", "m", "t", "p"];
+
+// A map field written in the given order.
+struct Entries<'a>(&'a [(&'static str, u32)]);
+
+impl<'a> Serialize for Entries<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_map(self.0.iter().cloned())
+    }
+}
+
+pub fn serialize<S>(params: &Argon2Params, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    let len = if params.version.is_some() { 2 } else { 1 };
+    let mut state = serializer.serialize_tuple_struct("Argon2Params", len)?;
+    if let Some(version) = params.version {
+        state.serialize_field(&Entries(&[("v", version)]))?;
+    }
+    state.serialize_field(&Entries(&[("m", params.m), ("t", params.t), ("p", params.p)]))?;
+    state.end()
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Argon2Params, D::Error>
+    where D: Deserializer<'de>
+{
+    struct ParamsVisitor;
+
+    impl<'de> Visitor<'de> for ParamsVisitor {
+        type Value = Argon2Params;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("argon2 version and parameters")
+        }
+
+        // The cost parameters are read from the next field only if the first
+        // has just the version, so a missing version field is not mistaken
+        // for the salt.
+        fn visit_seq<A>(self, mut seq: A) -> Result<Argon2Params, A::Error>
+            where A: SeqAccess<'de>
+        {
+            let mut params: BTreeMap<String, u32> = seq.next_element()?
+                .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+            if params.len() == 1 && params.contains_key("v") {
+                let cost: BTreeMap<String, u32> = seq.next_element()?
+                    .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                params.extend(cost);
+            }
+            let version = params.remove("v");
+            let mut take = |key| params.remove(key).ok_or_else(|| A::Error::missing_field(key));
+            let (m, t, p) = (take("m")?, take("t")?, take("p")?);
+            if let Some(key) = params.keys().next() {
+                return Err(A::Error::unknown_field(key, FIELDS));
+            }
+            Ok(Argon2Params { version, m, t, p })
+        }
+    }
+
+    deserializer.deserialize_tuple_struct("Argon2Params", 2, ParamsVisitor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {base64, from_str, to_string, Hashes};
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct Argon2Hash {
+        algorithm: Hashes,
+        #[serde(with = "super")]
+        params: Argon2Params,
+        #[serde(with = "base64")]
+        salt: Vec<u8>,
+        #[serde(with = "base64")]
+        hash: Vec<u8>,
+    }
+
+    #[test]
+    fn test_argon2_params() {
+        let versioned = "$argon2i$v=19$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA";
+        let hash: Argon2Hash = from_str(versioned).unwrap();
+        assert_eq!(hash.params,
+                   Argon2Params {
+                       version: Some(19),
+                       m: 65536,
+                       t: 2,
+                       p: 1,
+                   });
+        assert_eq!(hash.salt, b"somesalt");
+        assert_eq!(to_string(&hash).unwrap(), versioned);
+
+        let unversioned = "$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA";
+        let hash: Argon2Hash = from_str(unversioned).unwrap();
+        assert_eq!(hash.params.version, None);
+        assert_eq!((hash.params.m, hash.params.t, hash.params.p), (65536, 2, 1));
+        assert_eq!(hash.salt, b"somesalt");
+        assert_eq!(to_string(&hash).unwrap(), unversioned);
+
+        // The version may also be in the parameters field. Only `McfHash`
+        // reads it from the algorithm field.
+        let hash: Argon2Hash = from_str("$argon2i$v=19,m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA")
+            .unwrap();
+        assert_eq!(to_string(&hash).unwrap(), versioned);
+        assert!(from_str::<Argon2Hash>("$argon2i,v=19$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA")
+            .is_err());
+
+        assert!(from_str::<Argon2Hash>("$argon2i$v=19$m=65536,t=2$c29tZXNhbHQ$aGFzaA").is_err());
+        assert!(from_str::<Argon2Hash>("$argon2i$m=65536,t=2,p=1,x=1$c29tZXNhbHQ$aGFzaA")
+            .is_err());
+    }
+}
@@ -1,10 +1,16 @@
 use data_encoding::BASE64_NOPAD;
 use serde::de::{self, Deserialize, Deserializer, IntoDeserializer, Visitor};
 
+use std::collections::BTreeMap;
 use std::fmt::Display;
-use std::str::Split;
+use std::iter::{once, Peekable};
+use std::str::{Split, SplitN};
+
+use serde_json;
 
 use errors::*;
+use prelude::*;
+use {Dialect, Hashes};
 
 impl de::Error for Error {
     fn custom<T>(msg: T) -> Self
@@ -15,24 +21,694 @@ impl de::Error for Error {
 }
 
 /// Deserializer for the MCF format.
-pub struct McfDeserializer<'de, I: Iterator<Item = &'de str>>(I);
+pub struct McfDeserializer<'de, I: Iterator<Item = &'de str>> {
+    fields: Peekable<I>,
+    max_map_entries: usize,
+    // The whole input, if the fields are slices of it, for reporting the
+    // offsets of invalid fields.
+    input: Option<&'de str>,
+    // Names for the fields, if the outermost value is read as a map.
+    field_names: Option<&'static [&'static str]>,
+    // Whether the fields are the comma-separated elements of a sequence, in
+    // which a nested tuple takes the next elements in turn.
+    elements: bool,
+    // Whether the outermost struct must have exactly one field per member.
+    exact_fields: bool,
+    // Whether map values may be quoted, to allow commas in them.
+    quoted_values: bool,
+    // Whether the field is the missing value of a bare flag in a map.
+    bare_flag: bool,
+    dialect: Dialect,
+}
+
+/// The default limit on the number of entries in a single map field.
+pub const DEFAULT_MAX_MAP_ENTRIES: usize = 256;
+
+/// The limit on the length of the input used by `from_str_limited`, in bytes.
+pub const DEFAULT_MAX_LEN: usize = 1024;
 
 impl<'de> McfDeserializer<'de, Split<'de, char>> {
     /// Create a new deserializer from a string ref.
     pub fn new(input: &'de str) -> Self {
-        let mut iter = input.split('$');
+        McfDeserializer::with_dialect(input, Dialect::mcf())
+    }
+
+    /// Create a new deserializer for a dialect with other separators, such
+    /// as `:` between fields.
+    ///
+    /// As with `new`, the input starts with a field separator, which is
+    /// skipped.
+    pub fn with_dialect(input: &'de str, dialect: Dialect) -> Self {
+        let mut iter = input.split(dialect.field);
+        iter.next();
+        McfDeserializer { dialect, ..McfDeserializer::from_fields(iter) }.with_input(input)
+    }
+
+    /// Create a new deserializer, after checking that the input is at most
+    /// `limit` bytes long.
+    ///
+    /// Longer inputs give an `InputTooLong` error without being looked at
+    /// any further. This bounds the work done parsing untrusted input.
+    pub fn with_max_len(input: &'de str, limit: usize) -> Result<Self> {
+        if input.len() > limit {
+            return Err(ErrorKind::InputTooLong(input.len(), limit).into());
+        }
+        Ok(McfDeserializer::new(input))
+    }
+
+    /// Create a new deserializer for fields separated by `delimiter` rather
+    /// than `$`.
+    pub fn with_delimiter(input: &'de str, delimiter: char) -> Self {
+        McfDeserializer::with_dialect(input, Dialect { field: delimiter, ..Dialect::mcf() })
+    }
+}
+
+impl<'de> McfDeserializer<'de, SplitN<'de, char>> {
+    /// Create a new deserializer which splits the input into at most
+    /// `fields` fields, so the last one absorbs the rest of the input,
+    /// including any `$`.
+    ///
+    /// This is for formats whose final field may itself contain `$`.
+    pub fn with_greedy_tail(input: &'de str, fields: usize) -> Self {
+        McfDeserializer::greedy_tail_with_dialect(input, fields, Dialect::mcf())
+    }
+
+    /// As `with_greedy_tail`, for a dialect with other separators.
+    pub fn greedy_tail_with_dialect(input: &'de str, fields: usize, dialect: Dialect) -> Self {
+        let mut iter = input.splitn(fields + 1, dialect.field);
         iter.next();
-        McfDeserializer(iter)
+        McfDeserializer { dialect, ..McfDeserializer::from_fields(iter) }.with_input(input)
+    }
+}
+
+impl<'de> McfDeserializer<'de, LengthPrefixedFields<'de>> {
+    /// Create a new deserializer which understands length-prefixed fields.
+    ///
+    /// A field of the form `<len>:<data>` is read as exactly `len` bytes of
+    /// data, which may include `$`. This allows fields encoded with a custom
+    /// alphabet containing the delimiter.
+    pub fn with_length_prefixes(input: &'de str) -> Self {
+        McfDeserializer::length_prefixes_with_dialect(input, Dialect::mcf())
+    }
+
+    /// As `with_length_prefixes`, for a dialect with other separators.
+    pub fn length_prefixes_with_dialect(input: &'de str, dialect: Dialect) -> Self {
+        let fields = LengthPrefixedFields {
+            rest: input.strip_prefix(dialect.field),
+            delimiter: dialect.field,
+        };
+        McfDeserializer { dialect, ..McfDeserializer::from_fields(fields) }.with_input(input)
+    }
+}
+
+/// Iterator over delimited fields which may be length-prefixed.
+///
+/// See `McfDeserializer::with_length_prefixes`.
+pub struct LengthPrefixedFields<'de> {
+    rest: Option<&'de str>,
+    delimiter: char,
+}
+
+impl<'de> LengthPrefixedFields<'de> {
+    // If the remaining input starts with a well-formed `<len>:<data>` field,
+    // returns the data and the input following it.
+    fn prefixed_field(&self, rest: &'de str) -> Option<(&'de str, &'de str)> {
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 || !rest[digits..].starts_with(':') {
+            return None;
+        }
+        let start = digits + 1;
+        let end = start.checked_add(rest[..digits].parse().ok()?)?;
+        let after = rest.get(end..)?;
+        if after.is_empty() || after.starts_with(self.delimiter) {
+            Some((&rest[start..end], after))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'de> Iterator for LengthPrefixedFields<'de> {
+    type Item = &'de str;
+
+    fn next(&mut self) -> Option<&'de str> {
+        let rest = self.rest?;
+        let (field, after) = self.prefixed_field(rest).unwrap_or_else(|| {
+            match rest.find(self.delimiter) {
+                Some(idx) => (&rest[..idx], &rest[idx..]),
+                None => (rest, ""),
+            }
+        });
+        self.rest = after.strip_prefix(self.delimiter);
+        Some(field)
+    }
+}
+
+impl<'de, I: Iterator<Item = &'de str>> McfDeserializer<'de, I> {
+    // Create a deserializer over fields which have already been split.
+    fn from_fields(fields: I) -> Self {
+        McfDeserializer {
+            fields: fields.peekable(),
+            max_map_entries: DEFAULT_MAX_MAP_ENTRIES,
+            input: None,
+            field_names: None,
+            elements: false,
+            exact_fields: false,
+            quoted_values: false,
+            bare_flag: false,
+            dialect: Dialect::mcf(),
+        }
+    }
+
+    // Create a deserializer over the parts of a field, with the same limits
+    // and separators as this one.
+    fn nested<J: Iterator<Item = &'de str>>(&self, fields: J) -> McfDeserializer<'de, J> {
+        McfDeserializer {
+            max_map_entries: self.max_map_entries,
+            quoted_values: self.quoted_values,
+            dialect: self.dialect,
+            ..McfDeserializer::from_fields(fields)
+        }
+    }
+
+    // Create a deserializer over the elements of a sequence.
+    fn nested_elements<J: Iterator<Item = &'de str>>(&self, elements: J)
+                                                     -> McfDeserializer<'de, J> {
+        McfDeserializer { elements: true, ..self.nested(elements) }
+    }
+
+    // Deserialize the next field as a map. With `versioned`, a field with
+    // just a `v` entry is read together with the entries of the next, as in
+    // argon2's `$v=19$m=65536,t=2,p=1`.
+    fn deserialize_map_fields<V>(&mut self, versioned: bool, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        let v = self.fields.next().ok_or("no value found")?;
+        let json = v.starts_with('{');
+        let dialect = self.dialect;
+        if self.quoted_values && !json {
+            check_quoted(v, dialect)?;
+        }
+        if json {
+            return deserialize_json_map(v, self.max_map_entries, visitor);
+        }
+        let mut entries = map_tokens(v, dialect, self.quoted_values).count();
+        let mut next_field = None;
+        if versioned && entries == 1 &&
+           map_tokens(v, dialect, false).next().is_some_and(|(key, value)| {
+            key == "v" && value.is_some()
+        }) {
+            next_field = self.fields.next_if(|field| field.contains(dialect.key_value));
+        }
+        if let Some(field) = next_field {
+            if self.quoted_values {
+                check_quoted(field, dialect)?;
+            }
+            entries += map_tokens(field, dialect, self.quoted_values).count();
+        }
+        self.check_map_entries(entries)?;
+        let entries = map_tokens(v, dialect, self.quoted_values);
+        visitor.visit_map(MapEntries { de: self, entries, value: None, next_field })
+    }
+
+    fn check_map_entries(&self, entries: usize) -> Result<()> {
+        if entries > self.max_map_entries {
+            return Err(format!("map has {} entries, more than the limit of {}",
+                               entries,
+                               self.max_map_entries)
+                .into());
+        }
+        Ok(())
+    }
+
+    fn with_input(mut self, input: &'de str) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    // The byte offset in the input of the next field, if known.
+    fn next_offset(&mut self) -> Option<usize> {
+        let field = *self.fields.peek()?;
+        self.offset_of(field)
+    }
+
+    // The byte offset of `field` in the input, if it is a slice of it.
+    fn offset_of(&self, field: &str) -> Option<usize> {
+        let input = self.input?;
+        let offset = (field.as_ptr() as usize).checked_sub(input.as_ptr() as usize)?;
+        if offset <= input.len() { Some(offset) } else { None }
+    }
+
+    /// Consume the deserializer, returning the input which has not been
+    /// deserialized yet.
+    ///
+    /// This starts at the next field, without its leading `$`, so that the
+    /// rest of a hash can be handed to another parser. With length-prefixed
+    /// fields, it starts at the data of the next field. Once all fields are
+    /// consumed, this is empty.
+    pub fn into_remaining(mut self) -> &'de str {
+        match (self.input, self.next_offset()) {
+            (Some(input), Some(offset)) => &input[offset..],
+            _ => "",
+        }
+    }
+
+    /// Set the maximum number of entries allowed in a single map field, such
+    /// as the parameters of a hash. Defaults to `DEFAULT_MAX_MAP_ENTRIES`.
+    ///
+    /// This bounds the memory used when parsing untrusted input.
+    pub fn max_map_entries(mut self, max: usize) -> Self {
+        self.max_map_entries = max;
+        self
+    }
+
+    /// Name the fields, in order, so that the input can be read as a map.
+    ///
+    /// This is needed for structs with a `#[serde(flatten)]` field, which
+    /// serde reads as a map of field names to values rather than by
+    /// position. A name of `""` marks a `key=value,...` field, whose entries
+    /// are flattened into the map. For example, with the names `["algorithm",
+    /// "", "salt", "hash"]`, the parameters of `$alg$a=1,b=2$salt$hash` can
+    /// be collected by a flattened `HashMap<String, String>`.
+    ///
+    /// Serde buffers the values of such structs, so they are all strings, and
+    /// other types must be parsed by the struct's own `deserialize_with`.
+    pub fn field_names(mut self, names: &'static [&'static str]) -> Self {
+        self.field_names = Some(names);
+        self
+    }
+
+    /// Require the outermost struct to be read from exactly as many fields as
+    /// it has members, giving a `FieldCount` error otherwise.
+    ///
+    /// By default, fields after the last member are ignored, and missing
+    /// trailing fields are read as `None` by optional members. This is the
+    /// positional equivalent of `#[serde(deny_unknown_fields)]`.
+    pub fn exact_fields(mut self) -> Self {
+        self.exact_fields = true;
+        self
+    }
+
+    /// Allow map values to be wrapped in double quotes, as in
+    /// `note="a,b,c"`, so they may contain commas. The quotes are not part
+    /// of the value.
+    ///
+    /// This reads the output of the serializer's `quote_values` option.
+    pub fn quoted_values(mut self) -> Self {
+        self.quoted_values = true;
+        self
+    }
+
+    /// Deserialize into an existing value, reusing its allocations where
+    /// possible.
+    ///
+    /// This uses serde's `Deserialize::deserialize_in_place`, so strings and
+    /// sequences keep their buffers. Derived structs only keep those of their
+    /// fields with serde_derive's `deserialize_in_place` feature, which the
+    /// crate feature of the same name turns on. Other types are replaced.
+    ///
+    /// Without that feature, a derived struct is deserialized as with
+    /// `from_str` and then assigned, so none of its `String` or `Vec` fields
+    /// keep their buffers. The same goes for `McfHash`, whatever the
+    /// features; use `McfHash::parse_into` to reuse its buffers instead. On
+    /// error, the contents of `place` are unspecified.
+    pub fn deserialize_into<T: Deserialize<'de>>(&mut self, place: &mut T) -> Result<()> {
+        T::deserialize_in_place(self, place)
+    }
+
+    /// Collect the remaining fields, each with its index, without
+    /// interpreting them.
+    ///
+    /// This is a diagnostic aid for inspecting hashes of unknown formats.
+    /// Indices count from the first field not yet consumed, so for a new
+    /// deserializer index 0 is the algorithm.
+    pub fn into_pairs(self) -> Vec<(usize, &'de str)> {
+        self.fields.enumerate().collect()
     }
 }
 
 /// Deserialize the generic type V from a string.
+///
+/// The input must start with `$`, or a `MissingLeadingDelimiter` error is
+/// returned. A `McfDeserializer` created directly skips the first field
+/// without checking it.
 pub fn from_str<'de, V: Deserialize<'de>>(input: &'de str) -> Result<V> {
+    if !input.starts_with('$') {
+        return Err(ErrorKind::MissingLeadingDelimiter.into());
+    }
     V::deserialize(&mut McfDeserializer::new(input))
 }
 
+/// Deserialize a string into an existing value, reusing its allocations where
+/// possible.
+///
+/// This is for parsing many hashes in a loop. Derived structs only reuse
+/// their fields' buffers with the `deserialize_in_place` feature; see
+/// `McfDeserializer::deserialize_into`.
+pub fn from_str_into<'de, T: Deserialize<'de>>(input: &'de str, place: &mut T) -> Result<()> {
+    if !input.starts_with('$') {
+        return Err(ErrorKind::MissingLeadingDelimiter.into());
+    }
+    McfDeserializer::new(input).deserialize_into(place)
+}
+
+/// Deserialize the generic type V from a string of at most `DEFAULT_MAX_LEN`
+/// bytes.
+///
+/// This is for untrusted input, for which longer strings give an
+/// `InputTooLong` error before any parsing. Real hashes are far shorter.
+pub fn from_str_limited<'de, V: Deserialize<'de>>(input: &'de str) -> Result<V> {
+    if !input.starts_with('$') {
+        return Err(ErrorKind::MissingLeadingDelimiter.into());
+    }
+    V::deserialize(&mut McfDeserializer::with_max_len(input, DEFAULT_MAX_LEN)?)
+}
+
+/// Deserialize the generic type V from a string, after checking that it only
+/// contains printable, non-space ASCII.
+///
+/// This gives an `InvalidCharacter` error pointing at the first offending
+/// byte, rather than a confusing error from decoding the field it is in.
+pub fn from_str_strict<'de, V: Deserialize<'de>>(input: &'de str) -> Result<V> {
+    if let Some((position, &byte)) = input.as_bytes()
+        .iter()
+        .enumerate()
+        .find(|&(_, b)| !(0x21..=0x7e).contains(b)) {
+        return Err(ErrorKind::InvalidCharacter(position, byte).into());
+    }
+    from_str(input)
+}
+
+/// Deserialize a hash into a flat map with the keys `algorithm`, `params`,
+/// `salt` and `hash`.
+///
+/// The values are the raw field contents, so this works for any hash with
+/// the usual four-field layout, regardless of the algorithm.
+pub fn from_str_flat(input: &str) -> Result<BTreeMap<String, String>> {
+    #[derive(Deserialize)]
+    struct FlatHash {
+        algorithm: String,
+        params: String,
+        salt: String,
+        hash: String,
+    }
+
+    let flat: FlatHash = from_str(input)?;
+    let mut map = BTreeMap::new();
+    map.insert("algorithm".to_string(), flat.algorithm);
+    map.insert("params".to_string(), flat.params);
+    map.insert("salt".to_string(), flat.salt);
+    map.insert("hash".to_string(), flat.hash);
+    Ok(map)
+}
+
+/// The raw fields of a hash, borrowed from the input string.
+///
+/// Returned by `parse_parts`, for inspecting a hash before deserializing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct McfParts<'a> {
+    /// The algorithm identifier, such as `argon2i`.
+    pub algorithm: &'a str,
+    /// The parameter field, such as `m=65536,t=2,p=1`. Empty if there are no
+    /// parameters.
+    pub params: &'a str,
+    /// The encoded salt, or `None` for hashes of the form `$alg$params$hash`.
+    pub salt: Option<&'a str>,
+    /// The encoded hash.
+    pub hash: &'a str,
+}
+
+/// Split a hash into its raw fields, without allocating or decoding them.
+///
+/// As with `McfHash`, a hash with three fields is taken to have no salt.
+pub fn parse_parts<'a>(input: &'a str) -> Result<McfParts<'a>> {
+    parse_parts_with_dialect(input, Dialect::mcf())
+}
+
+/// As `parse_parts`, for a dialect with another field separator.
+pub fn parse_parts_with_dialect<'a>(input: &'a str, dialect: Dialect) -> Result<McfParts<'a>> {
+    let mut fields = input.split(dialect.field);
+    if fields.next() != Some("") {
+        return Err(ErrorKind::MissingLeadingDelimiter.into());
+    }
+    let parts = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(algorithm), Some(params), Some(salt), Some(hash)) => {
+            McfParts {
+                algorithm,
+                params,
+                salt: Some(salt),
+                hash,
+            }
+        }
+        (Some(algorithm), Some(params), Some(hash), None) => {
+            McfParts {
+                algorithm,
+                params,
+                salt: None,
+                hash,
+            }
+        }
+        _ => return Err("MCF hash must have three or four fields".into()),
+    };
+    if fields.next().is_some() {
+        return Err("too many fields in MCF hash".into());
+    }
+    Ok(parts)
+}
+
+/// Split a hash into its raw `$`-delimited fields, as owned strings, without
+/// interpreting them.
+///
+/// The empty field before the leading `$` is dropped; empty fields elsewhere
+/// are kept, so `$a$$c` gives `["a", "", "c"]`. Input without a leading `$`
+/// keeps its first field.
+pub fn fields(input: &str) -> Vec<String> {
+    fields_with_dialect(input, Dialect::mcf())
+}
+
+/// As `fields`, for a dialect with another field separator.
+pub fn fields_with_dialect(input: &str, dialect: Dialect) -> Vec<String> {
+    input.strip_prefix(dialect.field)
+        .unwrap_or(input)
+        .split(dialect.field)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Identify the algorithm of a hash from its first field, without parsing
+/// the rest.
+///
+/// This lets callers choose the type to deserialize into up front. Unknown
+/// identifiers are returned as `Hashes::Unknown`, as when parsing an
+/// `McfHash`; only a missing leading `$` is an error.
+pub fn peek_algorithm(input: &str) -> Result<Hashes> {
+    peek_algorithm_with_dialect(input, Dialect::mcf())
+}
+
+/// As `peek_algorithm`, for a dialect with another field separator.
+pub fn peek_algorithm_with_dialect(input: &str, dialect: Dialect) -> Result<Hashes> {
+    let field = input.strip_prefix(dialect.field)
+        .ok_or(ErrorKind::MissingLeadingDelimiter)?
+        .split(dialect.field)
+        .next()
+        .unwrap();
+    // The algorithm may be followed by an embedded version, as in `argon2i,v=19`.
+    Ok(::split_algorithm(field).0)
+}
+
+// Split a map field into its keys and values. An entry without an `=` is a
+// bare flag, and has no value.
+fn map_tokens<'de>(field: &'de str, dialect: Dialect, quoted: bool) -> MapTokens<'de> {
+    MapTokens {
+        rest: if field.is_empty() { None } else { Some(field) },
+        entry: dialect.entry,
+        key_value: dialect.key_value,
+        quoted,
+        malformed: false,
+    }
+}
+
+// Deserialize a JSON object field as a map, failing on trailing input or once
+// it has more than `max_entries` entries.
+fn deserialize_json_map<'de, V>(field: &'de str, max_entries: usize, visitor: V) -> Result<V::Value>
+    where V: Visitor<'de>
+{
+    // Passes the object on to the inner visitor, counting its entries.
+    struct Limited<V> {
+        visitor: V,
+        max_entries: usize,
+    }
+
+    impl<'de, V: Visitor<'de>> Visitor<'de> for Limited<V> {
+        type Value = V::Value;
+
+        fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+            self.visitor.expecting(formatter)
+        }
+
+        fn visit_map<A>(self, access: A) -> ::std::result::Result<V::Value, A::Error>
+            where A: de::MapAccess<'de>
+        {
+            self.visitor.visit_map(LimitedEntries {
+                access,
+                entries: 0,
+                max_entries: self.max_entries,
+            })
+        }
+    }
+
+    struct LimitedEntries<A> {
+        access: A,
+        entries: usize,
+        max_entries: usize,
+    }
+
+    impl<'de, A: de::MapAccess<'de>> de::MapAccess<'de> for LimitedEntries<A> {
+        type Error = A::Error;
+
+        fn next_key_seed<K>(&mut self, seed: K) -> ::std::result::Result<Option<K::Value>, A::Error>
+            where K: de::DeserializeSeed<'de>
+        {
+            let key = self.access.next_key_seed(seed)?;
+            if key.is_some() {
+                self.entries += 1;
+                if self.entries > self.max_entries {
+                    return Err(de::Error::custom(format!("map has more than the limit of {} \
+                                                          entries",
+                                                         self.max_entries)));
+                }
+            }
+            Ok(key)
+        }
+
+        fn next_value_seed<T>(&mut self, seed: T) -> ::std::result::Result<T::Value, A::Error>
+            where T: de::DeserializeSeed<'de>
+        {
+            self.access.next_value_seed(seed)
+        }
+
+        fn size_hint(&self) -> Option<usize> {
+            self.access.size_hint()
+        }
+    }
+
+    let mut json = serde_json::Deserializer::from_str(field);
+    let limited = Limited {
+        visitor,
+        max_entries,
+    };
+    let value = json.deserialize_map(limited).map_err(<Error as de::Error>::custom)?;
+    json.end().map_err(<Error as de::Error>::custom)?;
+    Ok(value)
+}
+
+// Check that every quoted value in a map field is closed, and followed by a
+// comma or the end of the field.
+fn check_quoted(field: &str, dialect: Dialect) -> Result<()> {
+    let mut tokens = map_tokens(field, dialect, true);
+    tokens.by_ref().count();
+    if tokens.malformed {
+        return Err(format!("unterminated quoted value in `{}`", field).into());
+    }
+    Ok(())
+}
+
+// Iterator over the entries of a map field. With `quoted`, a value starting
+// with `"` runs to the next `"`, and may contain commas.
+struct MapTokens<'de> {
+    // The entries not yet split, if any.
+    rest: Option<&'de str>,
+    entry: char,
+    key_value: char,
+    quoted: bool,
+    // Whether a quoted value was not closed properly.
+    malformed: bool,
+}
+
+impl<'de> Iterator for MapTokens<'de> {
+    type Item = (&'de str, Option<&'de str>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.rest?;
+        let key_end = rest.find([self.entry, self.key_value]).unwrap_or(rest.len());
+        let (key, after) = rest.split_at(key_end);
+        let (value, after) = match after.strip_prefix(self.key_value) {
+            Some(after) if self.quoted && after.starts_with('"') => {
+                match after[1..].find('"') {
+                    Some(end) if after[end + 2..].is_empty() ||
+                                 after[end + 2..].starts_with(self.entry) => {
+                        (Some(&after[1..end + 1]), &after[end + 2..])
+                    }
+                    _ => {
+                        self.malformed = true;
+                        (Some(after), "")
+                    }
+                }
+            }
+            Some(after) => {
+                let (value, after) = after.split_at(after.find(self.entry).unwrap_or(after.len()));
+                (Some(value), after)
+            }
+            None => (None, after),
+        };
+        self.rest = after.strip_prefix(self.entry);
+        Some((key, value))
+    }
+}
+
+// Reads the entries of a map field, giving a bare flag an empty value which
+// only a bool may take.
+struct MapEntries<'a, 'de: 'a, I: 'a + Iterator<Item = &'de str>> {
+    de: &'a McfDeserializer<'de, I>,
+    entries: MapTokens<'de>,
+    value: Option<Option<&'de str>>,
+    // A field whose entries follow those of this one.
+    next_field: Option<&'de str>,
+}
+
+impl<'a, 'de, I: Iterator<Item = &'de str>> de::MapAccess<'de> for MapEntries<'a, 'de, I> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where K: de::DeserializeSeed<'de>
+    {
+        if self.entries.rest.is_none() {
+            if let Some(field) = self.next_field.take() {
+                self.entries = map_tokens(field, self.de.dialect, self.de.quoted_values);
+            }
+        }
+        match self.entries.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(&mut self.de.nested(once(key))).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where V: de::DeserializeSeed<'de>
+    {
+        deserialize_entry_value(self.de, self.value.take().unwrap_or(None), seed)
+    }
+}
+
+// Deserialize the value of a map entry, which is `None` for a bare flag.
+fn deserialize_entry_value<'de, I, V>(de: &McfDeserializer<'de, I>,
+                                      value: Option<&'de str>,
+                                      seed: V)
+                                      -> Result<V::Value>
+    where I: Iterator<Item = &'de str>,
+          V: de::DeserializeSeed<'de>
+{
+    match value {
+        Some(value) => seed.deserialize(&mut de.nested(once(value))),
+        None => seed.deserialize(&mut McfDeserializer { bare_flag: true, ..de.nested(once("")) }),
+    }
+}
+
 // Macro which will attempt to parse the input value (either self.0 or
-// self.0.next()) into whichever type is used. The parsed value can then be
+// self.fields.next()) into whichever type is used. The parsed value can then be
 // deserialized by the visitor.
 macro_rules! forward_parsable_to_deserialize_any {
     ($($ty:ident => $meth:ident,)*) => {
@@ -48,7 +724,7 @@ macro_rules! forward_parsable_to_deserialize_any {
     ($(iter $ty:ident => $meth:ident,)*) => {
         $(
             fn $meth<V>(self, visitor: V) -> Result<V::Value> where V: de::Visitor<'de> {
-                if let Some(v) = self.0.next() {
+                if let Some(v) = self.fields.next() {
                     match v.parse::<$ty>() {
                         Ok(val) => val.into_deserializer().$meth(visitor),
                         Err(e) => Err(de::Error::custom(e))
@@ -63,14 +739,14 @@ macro_rules! forward_parsable_to_deserialize_any {
 }
 
 
-impl<'a, 'de, I: Iterator<Item = &'de str>> Deserializer<'de> for &'a mut McfDeserializer<'de, I> {
+impl<'de, I: Iterator<Item = &'de str>> Deserializer<'de> for &mut McfDeserializer<'de, I> {
     type Error = Error;
 
     // By default attempt to visit a string.
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(k) = self.0.next() {
+        if let Some(k) = self.fields.next() {
             visitor.visit_borrowed_str(k)
         } else {
             Err("No field to deserialize".into())
@@ -80,14 +756,24 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> Deserializer<'de> for &'a mut McfDes
     // A struct is deserialized by iterating through the expected fields, and
     // returning each value one-by-one.
     fn deserialize_struct<V>(self,
-                             _name: &'static str,
+                             name: &'static str,
                              fields: &'static [&'static str],
                              visitor: V)
                              -> Result<V::Value>
         where V: Visitor<'de>
     {
+        if name == ::VERSIONED_PARAMS {
+            return self.deserialize_map_fields(true, visitor);
+        }
         // TODO: could change this to visit_seq?
-        visitor.visit_map(McfWithFields(self, fields.to_vec().into_iter()))
+        if !::std::mem::replace(&mut self.exact_fields, false) {
+            return visitor.visit_map(McfWithFields(self, fields.iter().cloned(), ""));
+        }
+        visitor.visit_map(ExactFields {
+            inner: McfWithFields(self, fields.iter().cloned(), ""),
+            expected: fields.len(),
+            found: 0,
+        })
     }
 
     // Attempt to deserialize the enum by simply checking the next field for a
@@ -106,18 +792,19 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> Deserializer<'de> for &'a mut McfDes
     fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(k) = self.0.next() {
+        if let Some(k) = self.fields.next() {
             visitor.visit_borrowed_str(k)
         } else {
             Err("No field to deserialize".into())
         }
     }
 
-    // Deserialize a byte buf by first converting the field from base64.
+    // Deserialize a byte buf by first converting the field from base64. An
+    // empty field is an empty byte buf.
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(v) = self.0.next() {
+        if let Some(v) = self.fields.next() {
             visitor.visit_byte_buf(BASE64_NOPAD.decode(v.as_bytes())?)
         } else {
             Err("no value found".into())
@@ -129,72 +816,107 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> Deserializer<'de> for &'a mut McfDes
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(v) = self.0.next() {
-            let iter = v.split(',');
-            visitor.visit_seq(&mut McfDeserializer(iter))
+        if let Some(v) = self.fields.next() {
+            let iter = v.split(self.dialect.entry);
+            visitor.visit_seq(&mut self.nested_elements(iter))
         } else {
             Err("no value found".into())
         }
     }
 
-    // Deserializer a tuple by treating it as a sequence.
-    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    // Deserializer a tuple by treating it as a sequence. A tuple nested in a
+    // sequence is flattened into it, and reads the next `len` elements, so a
+    // `Vec<(u8, u8)>` is read from `1,2,3,4`.
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(v) = self.0.next() {
-            let iter = v.split(',');
-            visitor.visit_seq(&mut McfDeserializer(iter))
+        if self.elements {
+            return visitor.visit_seq(McfTupleFields(self, len));
+        }
+        if let Some(v) = self.fields.next() {
+            let iter = v.split(self.dialect.entry);
+            visitor.visit_seq(&mut self.nested_elements(iter))
         } else {
             Err("no value found".into())
         }
     }
 
-    // Deserialize a map by splitting on '=' and ',', returning each value one-
-    // by-one.
+    // Deserialize a map by splitting on ',' and then '=', returning each value
+    // one-by-one. An empty field is an empty map, and a field starting with
+    // '{' is parsed as a JSON object.
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(v) = self.0.next() {
-            let iter = v.split(|c| c == '=' || c == ',');
-            visitor.visit_map(&mut McfDeserializer(iter))
-        } else {
-            Err("no value found".into())
+        if let Some(names) = self.field_names.take() {
+            return visitor.visit_map(NamedFields {
+                de: self,
+                names: names.iter(),
+                entries: None,
+                value: None,
+            });
         }
+        self.deserialize_map_fields(false, visitor)
     }
 
-    // We consider a None value to be a missing value between two delimiters.
-    // Anything else is deserializer as a Some value.
-    //
-    // This currently only works for flat options.
+    // We consider a None value to be a missing value between two delimiters,
+    // or a trailing field which is absent entirely. Anything else is
+    // deserialized as a Some value, which continues from the current field so
+    // the inner value may span several fields.
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        if let Some(v) = self.0.next() {
-            match v {
-                "" => visitor.visit_none(),
-                v => visitor.visit_some(&mut McfDeserializer([v].iter().cloned())),
+        match self.fields.peek() {
+            Some(&"") => {
+                self.fields.next();
+                visitor.visit_none()
             }
-        } else {
-            Err("no value found".into())
+            Some(_) => visitor.visit_some(self),
+            None => visitor.visit_none(),
         }
     }
 
+    // A tuple struct is read like a struct, with each field in turn.
+    fn deserialize_tuple_struct<V>(self,
+                                   _name: &'static str,
+                                   len: usize,
+                                   visitor: V)
+                                   -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        visitor.visit_seq(McfTupleFields(self, len))
+    }
+
     forward_to_deserialize_any! {
         char str
         string bytes unit unit_struct newtype_struct
-        tuple_struct ignored_any
+        ignored_any
+    }
+
+    // Booleans may be written as `true`/`false` or `1`/`0`, or as a bare
+    // flag in a map.
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+        where V: Visitor<'de>
+    {
+        match self.fields.next() {
+            Some("true") | Some("1") => visitor.visit_bool(true),
+            Some("") if self.bare_flag => visitor.visit_bool(true),
+            Some("false") | Some("0") => visitor.visit_bool(false),
+            Some(v) => Err(format!("invalid boolean `{}`", v).into()),
+            None => Err("no value found".into()),
+        }
     }
 
     forward_parsable_to_deserialize_any! {
-        iter bool => deserialize_bool,
         iter u8 => deserialize_u8,
         iter u16 => deserialize_u16,
         iter u32 => deserialize_u32,
         iter u64 => deserialize_u64,
+        iter u128 => deserialize_u128,
         iter i8 => deserialize_i8,
         iter i16 => deserialize_i16,
         iter i32 => deserialize_i32,
         iter i64 => deserialize_i64,
+        iter i128 => deserialize_i128,
         iter f32 => deserialize_f32,
         iter f64 => deserialize_f64,
     }
@@ -202,7 +924,9 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> Deserializer<'de> for &'a mut McfDes
 
 // This is used to deserialize any map-like object by forcing the keys to be
 // whatever is returned from the iterator J.
-struct McfWithFields<'a, 'de: 'a, I: 'a + Iterator<Item=&'de str>, J: Iterator<Item=&'de str>>(&'a mut McfDeserializer<'de, I>, J);
+//
+// The last key is kept to report which field an error occurred in.
+struct McfWithFields<'a, 'de: 'a, I: 'a + Iterator<Item=&'de str>, J: Iterator<Item=&'de str>>(&'a mut McfDeserializer<'de, I>, J, &'de str);
 
 impl<'a, 'de, I: Iterator<Item = &'de str>, J: Iterator<Item = &'de str>> de::MapAccess<'de>
     for
@@ -213,7 +937,8 @@ impl<'a, 'de, I: Iterator<Item = &'de str>, J: Iterator<Item = &'de str>> de::Ma
     {
         // Take the next field from the iterator and deserialize it.
         if let Some(field) = self.1.next() {
-            seed.deserialize(&mut McfDeserializer([field].iter().cloned())).map(Some)
+            self.2 = field;
+            seed.deserialize(&mut self.0.nested(once(field))).map(Some)
         } else {
             Ok(None)
         }
@@ -222,12 +947,124 @@ impl<'a, 'de, I: Iterator<Item = &'de str>, J: Iterator<Item = &'de str>> de::Ma
     fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
         where V: de::DeserializeSeed<'de>
     {
-        // Continue to deserialize from the McfDeserializer
-        seed.deserialize(&mut *self.0)
+        // Continue to deserialize from the McfDeserializer, noting where the
+        // field started if it fails. Errors from a nested struct already
+        // point at the innermost field. If the input ran out before this
+        // field, the error names it as missing.
+        let offset = self.0.next_offset();
+        let missing = self.0.fields.peek().is_none();
+        seed.deserialize(&mut *self.0).map_err(|e| {
+            if missing {
+                return de::Error::custom(format!("missing field `{}`", self.2));
+            }
+            let offset = match offset {
+                Some(offset) => offset,
+                None => return e,
+            };
+            let reason = match *e.kind() {
+                ErrorKind::InvalidField(..) => return e,
+                ErrorKind::Custom(ref msg) => msg.clone(),
+                _ => e.to_string(),
+            };
+            ErrorKind::InvalidField(self.2.to_string(), offset, reason).into()
+        })
+    }
+}
+
+// Wraps `McfWithFields` to check that there is a field for every key, and
+// none left over, for `McfDeserializer::exact_fields`.
+struct ExactFields<'a, 'de: 'a, I: 'a + Iterator<Item=&'de str>, J: Iterator<Item=&'de str>> {
+    inner: McfWithFields<'a, 'de, I, J>,
+    expected: usize,
+    found: usize,
+}
+
+impl<'a, 'de, I: Iterator<Item = &'de str>, J: Iterator<Item = &'de str>> de::MapAccess<'de>
+    for
+    ExactFields<'a, 'de, I, J> {
+    type Error = Error;
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where K: de::DeserializeSeed<'de>
+    {
+        if self.found < self.expected && self.inner.0.fields.peek().is_none() {
+            return Err(ErrorKind::FieldCount(self.expected, self.found).into());
+        }
+        match self.inner.next_key_seed(seed)? {
+            Some(key) => {
+                self.found += 1;
+                Ok(Some(key))
+            }
+            None => {
+                let extra = self.inner.0.fields.by_ref().count();
+                if extra > 0 {
+                    return Err(ErrorKind::FieldCount(self.expected, self.found + extra).into());
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where V: de::DeserializeSeed<'de>
+    {
+        self.inner.next_value_seed(seed)
     }
 }
 
-impl<'a, 'de, I: Iterator<Item = &'de str>> de::MapAccess<'de> for &'a mut McfDeserializer<'de, I> {
+// Reads the fields as a map, keyed by the names given to
+// `McfDeserializer::field_names`.
+struct NamedFields<'a, 'de: 'a, I: 'a + Iterator<Item = &'de str>> {
+    de: &'a mut McfDeserializer<'de, I>,
+    names: ::std::slice::Iter<'static, &'static str>,
+    // The remaining entries of a flattened field, and the value of the last
+    // key read from them.
+    entries: Option<::std::vec::IntoIter<(&'de str, Option<&'de str>)>>,
+    value: Option<&'de str>,
+}
+
+impl<'a, 'de, I: Iterator<Item = &'de str>> de::MapAccess<'de> for NamedFields<'a, 'de, I> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+        where K: de::DeserializeSeed<'de>
+    {
+        loop {
+            if let Some((key, value)) = self.entries.as_mut().and_then(Iterator::next) {
+                self.value = value;
+                return seed.deserialize(&mut self.de.nested(once(key))).map(Some);
+            }
+            self.entries = None;
+            match self.names.next() {
+                Some(&"") => {
+                    let field = self.de.fields.next().unwrap_or("");
+                    let dialect = self.de.dialect;
+                    if self.de.quoted_values {
+                        check_quoted(field, dialect)?;
+                    }
+                    let entries = map_tokens(field, dialect, self.de.quoted_values)
+                        .collect::<Vec<_>>();
+                    self.de.check_map_entries(entries.len())?;
+                    self.entries = Some(entries.into_iter());
+                }
+                Some(name) if self.de.fields.peek().is_some() => {
+                    return seed.deserialize(&mut self.de.nested(once(*name))).map(Some);
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+        where V: de::DeserializeSeed<'de>
+    {
+        match self.entries {
+            Some(_) => deserialize_entry_value(self.de, self.value.take(), seed),
+            None => seed.deserialize(&mut *self.de),
+        }
+    }
+}
+
+impl<'de, I: Iterator<Item = &'de str>> de::MapAccess<'de> for &mut McfDeserializer<'de, I> {
     type Error = Error;
 
     // Similar to the above, but assumes all values are being returned from a
@@ -235,8 +1072,8 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> de::MapAccess<'de> for &'a mut McfDe
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
         where K: de::DeserializeSeed<'de>
     {
-        if let Some(field) = self.0.next() {
-            seed.deserialize(&mut McfDeserializer([field].iter().cloned())).map(Some)
+        if let Some(field) = self.fields.next() {
+            seed.deserialize(&mut self.nested(once(field))).map(Some)
         } else {
             Ok(None)
         }
@@ -259,8 +1096,8 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> de::EnumAccess<'de>
     fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
         where V: de::DeserializeSeed<'de>
     {
-        if let Some(value) = self.0.next() {
-            let val = seed.deserialize(&mut McfDeserializer([value].iter().cloned()))?;
+        if let Some(value) = self.fields.next() {
+            let val = seed.deserialize(&mut self.nested(once(value)))?;
             Ok((val, self))
         } else {
             Err(de::Error::custom("Not enough fields"))
@@ -271,9 +1108,9 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> de::EnumAccess<'de>
 
 // `VariantAccess` is provided to the `Visitor` to give it the ability to see
 // the content of the single variant that it decided to deserialize.
-impl<'a, 'de, I: Iterator<Item = &'de str>> de::VariantAccess<'de>
+impl<'de, I: Iterator<Item = &'de str>> de::VariantAccess<'de>
     for
-    &'a mut McfDeserializer<'de, I> {
+    &mut McfDeserializer<'de, I> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
@@ -287,12 +1124,12 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> de::VariantAccess<'de>
         seed.deserialize(self)
     }
 
-    // Tuple variants are represented in JSON as `{ NAME: [DATA...] }` so
-    // deserialize the sequence of data here.
-    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    // Tuple variants are written with each element in its own field, as in
+    // `$Variant$1$2`, so read the next `len` fields.
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
         where V: Visitor<'de>
     {
-        de::Deserializer::deserialize_seq(self, visitor)
+        visitor.visit_seq(McfTupleFields(self, len))
     }
 
     // Struct variants are represented in JSON as `{ NAME: { K: V, ... } }` so
@@ -304,13 +1141,40 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> de::VariantAccess<'de>
     }
 }
 
-impl<'a, 'de, I: Iterator<Item = &'de str>> de::SeqAccess<'de> for &'a mut McfDeserializer<'de, I> {
+// The remaining fields of a tuple struct, each read from the deserializer in
+// turn.
+struct McfTupleFields<'a, 'de: 'a, I: 'a + Iterator<Item = &'de str>>(
+    &'a mut McfDeserializer<'de, I>,
+    usize,
+);
+
+impl<'a, 'de, I: Iterator<Item = &'de str>> de::SeqAccess<'de> for McfTupleFields<'a, 'de, I> {
     type Error = Error;
+
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
         where T: de::DeserializeSeed<'de>
     {
-        if let Some(v) = self.0.next() {
-            seed.deserialize(&mut McfDeserializer::new(v)).map(Some)
+        if self.1 == 0 {
+            return Ok(None);
+        }
+        self.1 -= 1;
+        seed.deserialize(&mut *self.0).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.1)
+    }
+}
+
+impl<'de, I: Iterator<Item = &'de str>> de::SeqAccess<'de> for &mut McfDeserializer<'de, I> {
+    type Error = Error;
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+        where T: de::DeserializeSeed<'de>
+    {
+        // Each element is read from the remaining elements, which a nested
+        // tuple may take several of.
+        if self.fields.peek().is_some() {
+            seed.deserialize(&mut **self).map(Some)
         } else {
             Ok(None)
         }
@@ -321,6 +1185,7 @@ impl<'a, 'de, I: Iterator<Item = &'de str>> de::SeqAccess<'de> for &'a mut McfDe
 mod test {
     use serde_bytes;
     use std::collections::HashMap;
+    use Dialect;
 
     #[test]
     fn test_deserialize() {
@@ -356,4 +1221,763 @@ mod test {
         let ts = "$First$38$128";
         assert_eq!(super::from_str::<TestEnum>(ts).unwrap(), t);
     }
+
+    #[test]
+    fn test_deserialize_embedded_version() {
+        use {to_string, McfHash, Value};
+
+        let hash: McfHash = super::from_str("$argon2id,v=19$m=65536,t=2,p=1$c29tZXNhbHQ\
+                                             $CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc")
+            .unwrap();
+        assert_eq!(hash.algorithm, ::Hashes::Argon2id);
+        assert_eq!(hash.parameters["v"], Value::String("19".to_string()));
+        assert_eq!(hash.parameters["m"], Value::String("65536".to_string()));
+
+        // The version is written in a field of its own, as the reference
+        // implementation does, and reads back the same way.
+        let reference = "$argon2id$v=19$m=65536,t=2,p=1$c29tZXNhbHQ\
+                         $CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc";
+        assert_eq!(to_string(&hash).unwrap(), reference);
+        assert_eq!(super::from_str::<McfHash>(reference).unwrap(), hash);
+        assert_eq!(::McfHash::from_phc_str(reference).unwrap(), hash);
+
+        // Other formats see the version as just another parameter.
+        let json = hash.to_json().unwrap();
+        assert_eq!(json["parameters"]["v"], "19");
+        assert_eq!(::serde_json::from_value::<McfHash>(json).unwrap(), hash);
+
+        // A lone version is not followed by the cost parameters.
+        let version_only: McfHash = super::from_str("$argon2id$v=19$c29tZXNhbHQ$aGFzaA").unwrap();
+        assert_eq!(version_only.parameters.len(), 1);
+        assert_eq!(version_only.salt.as_deref(), Some(&b"somesalt"[..]));
+        assert_eq!(to_string(&version_only).unwrap(), "$argon2id$v=19$c29tZXNhbHQ$aGFzaA");
+    }
+
+    #[test]
+    fn test_enum_field_is_whole() {
+        use std::collections::BTreeMap;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        enum Algorithm {
+            A,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            algorithm: Algorithm,
+            params: BTreeMap<String, u32>,
+        }
+
+        // Only `McfHash` reads parameters from the algorithm field.
+        assert!(super::from_str::<TestStruct>("$A$y=2").is_ok());
+        assert!(super::from_str::<TestStruct>("$A,x=1$y=2").is_err());
+        assert!(super::from_str::<::Hashes>("$argon2i,v=19").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_nested_option() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Pair {
+            a: u8,
+            b: u8,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            pair: Option<Pair>,
+            c: u8,
+        }
+
+        let t = TestStruct {
+            pair: Some(Pair { a: 1, b: 2 }),
+            c: 3,
+        };
+        assert_eq!(super::from_str::<TestStruct>("$1$2$3").unwrap(), t);
+
+        let t = TestStruct { pair: None, c: 3 };
+        assert_eq!(super::from_str::<TestStruct>("$$3").unwrap(), t);
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TupleStruct {
+            pair: Option<(u8, u8)>,
+            c: u8,
+        }
+
+        let t = TupleStruct {
+            pair: Some((1, 2)),
+            c: 3,
+        };
+        assert_eq!(super::from_str::<TupleStruct>("$1,2$3").unwrap(), t);
+        let t = TupleStruct { pair: None, c: 3 };
+        assert_eq!(super::from_str::<TupleStruct>("$$3").unwrap(), t);
+        assert!(super::from_str::<TupleStruct>("$1$3").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_empty_bytes() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            p: u8,
+            #[serde(with="serde_bytes")]
+            salt: Vec<u8>,
+            #[serde(with="::base64")]
+            hash: Vec<u8>,
+        }
+
+        let t = TestStruct {
+            p: 12,
+            salt: vec![],
+            hash: vec![],
+        };
+        assert_eq!(super::from_str::<TestStruct>("$12$$").unwrap(), t);
+    }
+
+    #[test]
+    fn test_deserialize_length_prefixed() {
+        use encoding::Base64Custom;
+        use serde::{Deserialize, Deserializer};
+
+        lazy_static! {
+            // The standard alphabet, with `/` replaced by `$`.
+            static ref DOLLAR: Base64Custom = Base64Custom::new(
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+$").unwrap();
+        }
+
+        fn de_dollar<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+            DOLLAR.deserialize(d)
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            p: u8,
+            #[serde(deserialize_with = "de_dollar")]
+            hash: Vec<u8>,
+            q: u8,
+        }
+
+        let hash = vec![0xff, 0xff, 0x00];
+        let encoded = DOLLAR.encode(&hash);
+        assert_eq!(encoded, "$$8A");
+
+        let input = format!("$1${}:{}$2", encoded.len(), encoded);
+        let mut de = super::McfDeserializer::with_length_prefixes(&input);
+        let t = TestStruct { p: 1, hash, q: 2 };
+        assert_eq!(TestStruct::deserialize(&mut de).unwrap(), t);
+
+        // Without the prefix, the fields are split as usual.
+        let mut de = super::McfDeserializer::with_length_prefixes("$1$AAAA$2");
+        assert_eq!(TestStruct::deserialize(&mut de).unwrap().hash, [0, 0, 0]);
+
+        let star = Dialect { field: '*', ..Dialect::mcf() };
+        let input = format!("*1*{}:{}*2", encoded.len(), encoded);
+        let mut de = super::McfDeserializer::length_prefixes_with_dialect(&input, star);
+        assert_eq!(TestStruct::deserialize(&mut de).unwrap(), t);
+    }
+
+    #[test]
+    fn test_deserialize_flat() {
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let map = super::from_str_flat(argon_hash).unwrap();
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["algorithm", "hash", "params", "salt"]);
+        assert_eq!(map["algorithm"], "argon2i");
+        assert_eq!(map["params"], "m=262144,p=1,t=2");
+        assert_eq!(map["salt"], "c29tZXNhbHQ");
+        assert_eq!(map["hash"], "Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc");
+    }
+
+    #[test]
+    fn test_parse_parts() {
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let parts = super::parse_parts(argon_hash).unwrap();
+        assert_eq!(parts.algorithm, "argon2i");
+        assert_eq!(parts.params, "m=262144,p=1,t=2");
+        assert_eq!(parts.salt, Some("c29tZXNhbHQ"));
+        assert_eq!(parts.hash, "Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc");
+
+        // Each slice points into the original input.
+        let range = argon_hash.as_bytes().as_ptr_range();
+        for field in &[parts.algorithm, parts.params, parts.salt.unwrap(), parts.hash] {
+            assert!(range.contains(&field.as_ptr()));
+        }
+        assert_eq!(parts.hash.as_ptr(), argon_hash[argon_hash.len() - 43..].as_ptr());
+
+        let parts = super::parse_parts("$3$$iEb36u6PsRetBr3YMLdYbA").unwrap();
+        assert_eq!((parts.params, parts.salt, parts.hash), ("", None, "iEb36u6PsRetBr3YMLdYbA"));
+
+        assert!(super::parse_parts("argon2i$m=1$c2FsdA$aGFzaA").is_err());
+        assert!(super::parse_parts("$argon2i$m=1").is_err());
+        assert!(super::parse_parts("$argon2i$m=1$c2FsdA$aGFzaA$").is_err());
+
+        let colon = Dialect { field: ':', ..Dialect::mcf() };
+        let parts = super::parse_parts_with_dialect(":argon2i:m=1:c2FsdA:aGFzaA", colon).unwrap();
+        assert_eq!((parts.algorithm, parts.params, parts.salt, parts.hash),
+                   ("argon2i", "m=1", Some("c2FsdA"), "aGFzaA"));
+        assert!(super::parse_parts_with_dialect(argon_hash, colon).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_strict() {
+        use errors::ErrorKind;
+        use McfHash;
+
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        assert!(super::from_str_strict::<McfHash>(argon_hash).is_ok());
+
+        let accented = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHé\
+                        $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        match *super::from_str_strict::<McfHash>(accented).unwrap_err().kind() {
+            ErrorKind::InvalidCharacter(position, byte) => assert_eq!((position, byte), (36, 0xc3)),
+            ref e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_invalid_field_offset() {
+        use errors::ErrorKind;
+        use serde_bytes::ByteBuf;
+        use McfHash;
+
+        // The hash field starts at byte 28.
+        let input = "$argon2i$m=1,t=1,p=1$c2FsdA$aGF!zaA";
+        let err = super::from_str::<McfHash>(input).unwrap_err();
+        match *err.kind() {
+            ErrorKind::InvalidField(ref field, offset, _) => {
+                assert_eq!((field.as_str(), offset), ("hash", 28))
+            }
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(err.to_string().starts_with("invalid hash field at byte 28: invalid base64"));
+
+        let mut hash: McfHash = super::from_str("$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA").unwrap();
+        match *hash.parse_into(input).unwrap_err().kind() {
+            ErrorKind::InvalidField(ref field, offset, _) => {
+                assert_eq!((field.as_str(), offset), ("hash", 28))
+            }
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            algorithm: String,
+            salt: ByteBuf,
+        }
+        assert_eq!(super::from_str::<TestStruct>("$alg$c2FsdA").unwrap(),
+                   TestStruct {
+                       algorithm: "alg".to_string(),
+                       salt: ByteBuf::from(b"salt".to_vec()),
+                   });
+        match *super::from_str::<TestStruct>("$alg$c2F!sdA").unwrap_err().kind() {
+            ErrorKind::InvalidField(ref field, offset, _) => {
+                assert_eq!((field.as_str(), offset), ("salt", 5))
+            }
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_into_pairs() {
+        let de = super::McfDeserializer::new("$argon2i$m=1,t=2$c29tZXNhbHQ$$aGFzaA");
+        assert_eq!(de.into_pairs(),
+                   [(0, "argon2i"), (1, "m=1,t=2"), (2, "c29tZXNhbHQ"), (3, ""), (4, "aGFzaA")]);
+    }
+
+    #[test]
+    fn test_fields() {
+        use super::fields;
+
+        assert_eq!(fields("$a$b$c"), ["a", "b", "c"]);
+        assert_eq!(fields("$a$$c"), ["a", "", "c"]);
+        assert_eq!(fields("a$b"), ["a", "b"]);
+
+        let colon = Dialect { field: ':', ..Dialect::mcf() };
+        assert_eq!(super::fields_with_dialect(":a:b$c", colon), ["a", "b$c"]);
+    }
+
+    #[test]
+    fn test_byte_buf() {
+        use serde_bytes::ByteBuf;
+        use to_string;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            p: u8,
+            salt: ByteBuf,
+            hash: ByteBuf,
+        }
+
+        let t = TestStruct {
+            p: 1,
+            salt: ByteBuf::from(b"somesalt".to_vec()),
+            hash: ByteBuf::from(vec![0x12, 0x23, 0x34]),
+        };
+        let encoded = to_string(&t).unwrap();
+        assert_eq!(encoded, "$1$c29tZXNhbHQ$EiM0");
+        assert_eq!(super::from_str::<TestStruct>(&encoded).unwrap(), t);
+        assert!(super::from_str::<TestStruct>("$1$c29tZXNhbHQ$!!").is_err());
+    }
+
+    #[test]
+    fn test_peek_algorithm() {
+        use Hashes;
+
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let bcrypt_hash = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+        assert_eq!(super::peek_algorithm(argon_hash).unwrap(), Hashes::Argon2i);
+        assert_eq!(super::peek_algorithm(bcrypt_hash).unwrap(), Hashes::Bcrypta);
+        assert_eq!(super::peek_algorithm("$argon2id,v=19$garbage").unwrap(), Hashes::Argon2id);
+
+        assert_eq!(super::peek_algorithm("$nope$m=1").unwrap(),
+                   Hashes::Unknown("nope".to_string()));
+        assert_eq!(super::peek_algorithm("$argon2id;v=19$garbage").unwrap(), Hashes::Argon2id);
+        let colon = Dialect { field: ':', ..Dialect::mcf() };
+        assert_eq!(super::peek_algorithm_with_dialect(":argon2i:m=1", colon).unwrap(),
+                   Hashes::Argon2i);
+        assert!(super::peek_algorithm("argon2i$m=1").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_seq() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct TestStruct {
+            list: Vec<u8>,
+            pair: Option<(u8, u8)>,
+            names: Vec<String>,
+        }
+
+        let t = TestStruct {
+            list: vec![1, 2, 3],
+            pair: Some((4, 5)),
+            names: vec!["a".to_string(), "b".to_string()],
+        };
+        assert_eq!(super::from_str::<TestStruct>("$1,2,3$4,5$a,b").unwrap(), t);
+        assert_eq!(super::from_str::<Vec<u8>>("$7").unwrap(), [7]);
+    }
+
+    #[test]
+    fn test_max_map_entries() {
+        use serde::Deserialize;
+        use McfHash;
+
+        let params = (0..300).map(|i| format!("p{}={}", i, i)).collect::<Vec<_>>().join(",");
+        let input = format!("$argon2i${}$c29tZXNhbHQ$aGFzaA", params);
+        let err = super::from_str::<McfHash>(&input).unwrap_err();
+        assert!(err.to_string().contains("300 entries"));
+
+        let mut de = super::McfDeserializer::new(&input).max_map_entries(300);
+        assert_eq!(McfHash::deserialize(&mut de).unwrap().parameters.len(), 300);
+
+        // JSON parameters are limited too.
+        let input = "$argon2i${\"m\":1,\"t\":1,\"p\":1}$c29tZXNhbHQ$aGFzaA";
+        let mut de = super::McfDeserializer::new(input).max_map_entries(2);
+        let err = McfHash::deserialize(&mut de).unwrap_err();
+        assert!(err.to_string().contains("limit of 2 entries"));
+        let mut de = super::McfDeserializer::new(input).max_map_entries(3);
+        assert_eq!(McfHash::deserialize(&mut de).unwrap().parameters.len(), 3);
+    }
+
+    #[test]
+    fn test_json_trailing_input() {
+        use McfHash;
+
+        let hash: McfHash = super::from_str("$custom${\"x\":1} $c2FsdA$aGFzaA").unwrap();
+        assert_eq!(hash.parameters.len(), 1);
+        assert!(super::from_str::<McfHash>("$custom${\"x\":1}garbage$c2FsdA$aGFzaA").is_err());
+        assert!(super::from_str::<McfHash>("$custom${\"x\":1}{}$c2FsdA$aGFzaA").is_err());
+    }
+
+    #[test]
+    fn test_nested_maps() {
+        use std::collections::BTreeMap;
+        use serde::Deserialize;
+        use Dialect;
+
+        type Maps = Vec<BTreeMap<String, u32>>;
+
+        let mut de = super::McfDeserializer::new("$a=1,b=2").max_map_entries(0);
+        let err = Maps::deserialize(&mut de).unwrap_err();
+        assert!(err.to_string().contains("more than the limit of 0"));
+
+        // Maps in a sequence are split with the same dialect.
+        let dialect = Dialect { entry: ';', key_value: ':', ..Dialect::mcf() };
+        let mut de = super::McfDeserializer::with_dialect("$a:1;b:2", dialect);
+        let maps = Maps::deserialize(&mut de).unwrap();
+        assert_eq!(maps.len(), 2);
+        assert_eq!((maps[0]["a"], maps[1]["b"]), (1, 2));
+    }
+
+    #[test]
+    fn test_max_len() {
+        use serde::Deserialize;
+        use errors::ErrorKind;
+        use McfHash;
+
+        let input = "$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA";
+        assert!(super::from_str_limited::<McfHash>(input).is_ok());
+        let mut de = super::McfDeserializer::with_max_len(input, input.len()).unwrap();
+        assert!(McfHash::deserialize(&mut de).is_ok());
+        match *super::McfDeserializer::with_max_len(input, 10).err().unwrap().kind() {
+            ErrorKind::InputTooLong(len, 10) => assert_eq!(len, input.len()),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+
+        // Rejected up front, even though it would fail to parse anyway.
+        let huge = "$".repeat(super::DEFAULT_MAX_LEN + 1);
+        match *super::from_str_limited::<McfHash>(&huge).unwrap_err().kind() {
+            ErrorKind::InputTooLong(..) => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_bare_flags() {
+        use {McfHash, Value};
+
+        let hash: McfHash = super::from_str("$scrypt$ln=10,r=8,raw,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(hash.parameters.keys().collect::<Vec<_>>(), ["ln", "r", "raw", "p"]);
+        assert_eq!(hash.parameters["raw"], Value::String(String::new()));
+        assert_eq!(hash.parameters["p"], Value::String("1".to_string()));
+
+        let map: HashMap<String, String> = super::from_str("$a=1,b,c=x=y").unwrap();
+        assert_eq!(map["a"], "1");
+        assert_eq!(map["b"], "");
+        assert_eq!(map["c"], "x=y");
+
+        let flags: HashMap<String, bool> = super::from_str("$fips,raw=0,debug=1").unwrap();
+        assert!(flags["fips"]);
+        assert!(!flags["raw"]);
+        assert!(flags["debug"]);
+        assert!(super::from_str::<HashMap<String, bool>>("$fips=").is_err());
+
+        #[derive(Debug, Deserialize)]
+        struct Flag {
+            algorithm: String,
+            flag: bool,
+            salt: String,
+        }
+        let err = super::from_str::<Flag>("$alg$$salt").unwrap_err();
+        assert!(err.to_string().contains("invalid boolean"), "{}", err);
+        let flag: Flag = super::from_str("$alg$true$salt").unwrap();
+        assert!(flag.flag);
+        assert_eq!((flag.algorithm.as_str(), flag.salt.as_str()), ("alg", "salt"));
+    }
+
+    #[test]
+    fn test_padded_values() {
+        use {to_string, McfHash, Value};
+
+        let input = "$custom$data=aGVsbG8=,key=YQ==,n=1$c2FsdA$aGFzaA";
+        let hash: McfHash = super::from_str(input).unwrap();
+        assert_eq!(hash.parameters.keys().collect::<Vec<_>>(), ["data", "key", "n"]);
+        assert_eq!(hash.parameters["data"], Value::String("aGVsbG8=".to_string()));
+        assert_eq!(hash.parameters["key"], Value::String("YQ==".to_string()));
+        assert_eq!(to_string(&hash).unwrap(), input);
+    }
+
+    #[test]
+    fn test_flatten() {
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Flattened {
+            algorithm: String,
+            #[serde(flatten)]
+            params: HashMap<String, String>,
+            salt: String,
+            hash: String,
+        }
+
+        const NAMES: &[&str] = &["algorithm", "", "salt", "hash"];
+        let mut de = super::McfDeserializer::new("$alg$a=1,b=2$salt$hash").field_names(NAMES);
+        let flat = Flattened::deserialize(&mut de).unwrap();
+        assert_eq!(flat.algorithm, "alg");
+        assert_eq!(flat.params.len(), 2);
+        assert_eq!((flat.params["a"].as_str(), flat.params["b"].as_str()), ("1", "2"));
+        assert_eq!((flat.salt.as_str(), flat.hash.as_str()), ("salt", "hash"));
+
+        let mut de = super::McfDeserializer::new("$alg$$salt$hash").field_names(NAMES);
+        assert!(Flattened::deserialize(&mut de).unwrap().params.is_empty());
+        let mut de = super::McfDeserializer::new("$alg$a=1$salt").field_names(NAMES);
+        assert!(Flattened::deserialize(&mut de).is_err());
+    }
+
+    #[test]
+    fn test_greedy_tail() {
+        use serde::Deserialize;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Nested {
+            algorithm: String,
+            salt: String,
+            inner: String,
+        }
+
+        let input = "$wrap$c2FsdA$2b$10$ckjEeyTD6estWyoofn4ERO";
+        let mut de = super::McfDeserializer::with_greedy_tail(input, 3);
+        let nested = Nested::deserialize(&mut de).unwrap();
+        assert_eq!(nested.salt, "c2FsdA");
+        assert_eq!(nested.inner, "2b$10$ckjEeyTD6estWyoofn4ERO");
+
+        // By default the tail is split as usual.
+        assert_eq!(super::from_str::<Nested>(input).unwrap().inner, "2b");
+
+        let colon = Dialect { field: ':', ..Dialect::mcf() };
+        let input = ":wrap:c2FsdA:a:b";
+        let mut de = super::McfDeserializer::greedy_tail_with_dialect(input, 3, colon);
+        assert_eq!(Nested::deserialize(&mut de).unwrap().inner, "a:b");
+    }
+
+    #[test]
+    fn test_missing_leading_delimiter() {
+        use errors::ErrorKind;
+        use McfHash;
+
+        let input = "argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA";
+        match *super::from_str::<McfHash>(input).unwrap_err().kind() {
+            ErrorKind::MissingLeadingDelimiter => {}
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(super::from_str::<McfHash>(&format!("${}", input)).is_ok());
+        assert!(super::parse_parts(input).is_err());
+        assert!(super::peek_algorithm(input).is_err());
+    }
+
+    #[test]
+    fn test_into_remaining() {
+        use serde::Deserialize;
+        use Hashes;
+
+        let input = "$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA";
+        let mut de = super::McfDeserializer::new(input);
+        assert_eq!(Hashes::deserialize(&mut de).unwrap(), Hashes::Argon2i);
+        assert_eq!(de.into_remaining(), "m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA");
+
+        let mut de = super::McfDeserializer::new("$argon2i");
+        assert_eq!(Hashes::deserialize(&mut de).unwrap(), Hashes::Argon2i);
+        assert_eq!(de.into_remaining(), "");
+        assert_eq!(super::McfDeserializer::new(input).into_remaining(), &input[1..]);
+    }
+
+    #[test]
+    fn test_nested_tuples() {
+        use to_string;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            pairs: Vec<(u8, u8)>,
+            wide: Vec<(u8, u16)>,
+            nested: (u8, (u8, u8)),
+        }
+
+        let value = TestStruct {
+            pairs: vec![(1, 2), (3, 4)],
+            wide: vec![(1, 300)],
+            nested: (5, (6, 7)),
+        };
+        let encoded = to_string(&value).unwrap();
+        assert_eq!(encoded, "$1,2,3,4$1,300$5,6,7");
+        assert_eq!(super::from_str::<TestStruct>(&encoded).unwrap(), value);
+
+        // Each tuple takes as many elements as it has.
+        assert!(super::from_str::<Vec<(u8, u8)>>("$1,2,3").is_err());
+        assert!(super::from_str::<(u8, (u8, u8))>("$1,2").is_err());
+    }
+
+    #[test]
+    fn test_enum_variants() {
+        use to_string;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Argon2Params {
+            m: u32,
+            t: u32,
+            p: u32,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        enum Params {
+            Argon2(Argon2Params),
+            Bcrypt(u8),
+            Scrypt { ln: u8, r: u32 },
+            Pbkdf2(u32, String),
+            Plain,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Hash {
+            params: Params,
+            salt: String,
+        }
+
+        let cases = vec![(Params::Argon2(Argon2Params { m: 65536, t: 2, p: 1 }),
+                          "$Argon2$65536$2$1$salt"),
+                         (Params::Bcrypt(10), "$Bcrypt$10$salt"),
+                         (Params::Scrypt { ln: 16, r: 8 }, "$Scrypt$16$8$salt"),
+                         (Params::Pbkdf2(29000, "sha256".to_string()), "$Pbkdf2$29000$sha256$salt"),
+                         (Params::Plain, "$Plain$salt")];
+        for (params, encoded) in cases {
+            let hash = Hash { params, salt: "salt".to_string() };
+            assert_eq!(to_string(&hash).unwrap(), encoded);
+            assert_eq!(super::from_str::<Hash>(encoded).unwrap(), hash);
+        }
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        #[serde(tag = "algorithm", content = "params")]
+        enum Adjacent {
+            Argon2(Argon2Params),
+            Bcrypt(u8),
+        }
+
+        let adjacent = Adjacent::Argon2(Argon2Params { m: 65536, t: 2, p: 1 });
+        assert_eq!(to_string(&adjacent).unwrap(), "$Argon2$65536$2$1");
+        assert_eq!(super::from_str::<Adjacent>("$Argon2$65536$2$1").unwrap(), adjacent);
+        assert_eq!(super::from_str::<Adjacent>("$Bcrypt$10").unwrap(), Adjacent::Bcrypt(10));
+    }
+
+    #[test]
+    fn test_dialect() {
+        use serde::Deserialize;
+        use ser::{to_string_with_options, Options};
+        use {Dialect, McfHash};
+
+        let hash: McfHash = super::from_str("$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA").unwrap();
+        let dialect = Dialect {
+            field: ':',
+            entry: ';',
+            key_value: '=',
+        };
+        let options = Options { dialect, ..Default::default() };
+        let encoded = to_string_with_options(&hash, options).unwrap();
+        assert_eq!(encoded, ":argon2i:m=1;t=1;p=1:c2FsdA:aGFzaA");
+        let mut de = super::McfDeserializer::with_dialect(&encoded, dialect);
+        assert_eq!(McfHash::deserialize(&mut de).unwrap(), hash);
+
+        // Sequences and versions in the algorithm field use the same
+        // separators.
+        let versioned = ":argon2i;v=19:m=1;t=1;p=1:c2FsdA:aGFzaA";
+        let mut de = super::McfDeserializer::with_dialect(versioned, dialect);
+        assert_eq!(McfHash::deserialize(&mut de).unwrap().parameters["v"], "19");
+        let mut de = super::McfDeserializer::with_dialect(":1;2;3", dialect);
+        assert_eq!(Vec::<u8>::deserialize(&mut de).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_missing_field() {
+        use errors::ErrorKind;
+
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize)]
+        struct Hash {
+            algorithm: String,
+            rounds: u32,
+            #[serde(with = "::base64")]
+            salt: Vec<u8>,
+            #[serde(with = "::base64")]
+            hash: Vec<u8>,
+        }
+
+        let message = |input| match *super::from_str::<Hash>(input).unwrap_err().kind() {
+            ErrorKind::Custom(ref msg) => msg.clone(),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        };
+        assert!(super::from_str::<Hash>("$alg$1000$c2FsdA$aGFzaA").is_ok());
+        assert_eq!(message("$alg$1000"), "missing field `salt`");
+        assert_eq!(message("$alg"), "missing field `rounds`");
+        // A field which is present but invalid is still reported as such.
+        let err = super::from_str::<Hash>("$alg$x$c2FsdA$aGFzaA").unwrap_err();
+        assert!(err.to_string().contains("invalid rounds field"));
+    }
+
+    #[test]
+    fn test_exact_fields() {
+        use serde::Deserialize;
+        use errors::ErrorKind;
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Hash {
+            algorithm: String,
+            params: String,
+            salt: String,
+            hash: Option<String>,
+        }
+
+        let parse = |input| Hash::deserialize(&mut super::McfDeserializer::new(input).exact_fields());
+        assert_eq!(parse("$alg$a=1$salt$hash").unwrap().hash, Some("hash".to_string()));
+        // Without the check, the extra field is ignored and the missing one
+        // is `None`.
+        assert!(super::from_str::<Hash>("$alg$a=1$salt$hash$extra").is_ok());
+        assert!(super::from_str::<Hash>("$alg$a=1$salt").unwrap().hash.is_none());
+
+        match *parse("$alg$a=1$salt$hash$extra$more").unwrap_err().kind() {
+            ErrorKind::FieldCount(4, 6) => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+        match *parse("$alg$a=1$salt").unwrap_err().kind() {
+            ErrorKind::FieldCount(4, 3) => (),
+            ref kind => panic!("unexpected error: {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_from_str_into() {
+        #[derive(Debug, Default, Deserialize, PartialEq)]
+        struct Hash {
+            algorithm: String,
+            params: Vec<u32>,
+            hash: String,
+        }
+
+        let mut hash = Hash::default();
+        super::from_str_into("$pbkdf2$1000,2000$aGFzaGhhc2g", &mut hash).unwrap();
+        assert_eq!(hash, super::from_str("$pbkdf2$1000,2000$aGFzaGhhc2g").unwrap());
+
+        // Shorter values are written over the existing buffers, if the
+        // derived struct has its own `deserialize_in_place`.
+        let buffers = (hash.params.as_ptr(), hash.hash.as_ptr());
+        super::from_str_into("$pbkdf2$5$aGFzaA", &mut hash).unwrap();
+        assert_eq!(hash, super::from_str("$pbkdf2$5$aGFzaA").unwrap());
+        if cfg!(feature = "deserialize_in_place") {
+            assert_eq!((hash.params.as_ptr(), hash.hash.as_ptr()), buffers);
+        }
+
+        // Top-level strings are always reused.
+        let mut text = String::with_capacity(16);
+        let buffer = text.as_ptr();
+        super::from_str_into("$argon2i", &mut text).unwrap();
+        assert_eq!((text.as_str(), text.as_ptr()), ("argon2i", buffer));
+
+        assert!(super::from_str_into("pbkdf2$5$aGFzaA", &mut hash).is_err());
+    }
+
+    #[test]
+    fn test_quoted_values() {
+        use serde::Deserialize;
+        use ser::{to_string_with_options, Options};
+        use McfHash;
+
+        let mut hash: McfHash = super::from_str("$custom$a=1$c2FsdA$aGFzaA").unwrap();
+        hash.parameters.insert("note".to_string(), "a,b,c".into());
+        hash.parameters.insert("b".to_string(), "x=y".into());
+        let options = Options { quote_values: true, ..Default::default() };
+        let encoded = to_string_with_options(&hash, options.clone()).unwrap();
+        assert_eq!(encoded, "$custom$a=1,note=\"a,b,c\",b=x=y$c2FsdA$aGFzaA");
+
+        let mut de = super::McfDeserializer::new(&encoded).quoted_values();
+        assert_eq!(McfHash::deserialize(&mut de).unwrap(), hash);
+        // Without quoting, the value is split up.
+        assert!(super::from_str::<McfHash>(&encoded).unwrap().parameters["note"] != "a,b,c");
+
+        let mut de = super::McfDeserializer::new("$custom$note=\"a,b$c2FsdA$aGFzaA")
+            .quoted_values();
+        assert!(McfHash::deserialize(&mut de).is_err());
+        let mut de = super::McfDeserializer::new("$custom$note=\"a\"b$c2FsdA$aGFzaA")
+            .quoted_values();
+        assert!(McfHash::deserialize(&mut de).is_err());
+
+        hash.parameters.insert("quote".to_string(), "\"a,b\"".into());
+        assert!(to_string_with_options(&hash, options).is_err());
+    }
 }
@@ -0,0 +1,34 @@
+//! The separators used to split a hash into fields, and fields into entries.
+
+/// The characters separating the parts of a hash.
+///
+/// In MCF, as in `$argon2i$m=65536,t=2$salt$hash`, fields are separated by
+/// `$`, map entries and sequence elements by `,`, and keys from values by
+/// `=`. Other dialects can be read with `McfDeserializer::with_dialect`, and
+/// written with the serializer's `dialect` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    /// The separator between fields, and before the first one.
+    pub field: char,
+    /// The separator between map entries and sequence elements.
+    pub entry: char,
+    /// The separator between a map key and its value.
+    pub key_value: char,
+}
+
+impl Dialect {
+    /// The separators of the Modular Crypt Format: `$`, `,` and `=`.
+    pub fn mcf() -> Dialect {
+        Dialect {
+            field: '$',
+            entry: ',',
+            key_value: '=',
+        }
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect::mcf()
+    }
+}
@@ -1,4 +1,9 @@
-/// Additional methods to deserialize to/from byte arrays encoded in base64.
+//! Additional methods to deserialize to/from byte arrays encoded in base64.
+
+use data_encoding::{Encoding, Specification};
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+use prelude::*;
 
 /// Helper methods for serializing byte arryays to/from base64 encoded format.
 pub mod base64 {
@@ -6,6 +11,8 @@ pub mod base64 {
     use data_encoding::BASE64_NOPAD;
     use serde::de::Error;
 
+    use prelude::*;
+
     pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
         where T: AsRef<[u8]>,
               S: Serializer
@@ -22,33 +29,129 @@ pub mod base64 {
             .map_err(|e| Error::custom(e.to_string()))
             })?
     }
-}
 
+    /// Helper methods for optional byte arrays, where `None` is an empty or
+    /// missing field.
+    pub mod option {
+        use std::fmt;
+        use serde::{Deserializer, Serializer};
+        use serde::de::{Error, Visitor};
 
-pub mod base64bcrypt {
-    use serde::{Deserialize, Deserializer, Serializer};
-    use serde::de::Error;
+        use prelude::*;
+
+        pub fn serialize<T, S>(bytes: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+            where T: AsRef<[u8]>,
+                  S: Serializer
+        {
+            match *bytes {
+                Some(ref bytes) => super::serialize(bytes, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, T: From<Vec<u8>>, D>(deserializer: D) -> Result<Option<T>, D::Error>
+            where D: Deserializer<'de>
+        {
+            struct OptionVisitor<T>(::std::marker::PhantomData<T>);
+
+            impl<'de, T: From<Vec<u8>>> Visitor<'de> for OptionVisitor<T> {
+                type Value = Option<T>;
+
+                fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                    formatter.write_str("an optional base64 string")
+                }
 
+                fn visit_none<E: Error>(self) -> Result<Option<T>, E> {
+                    Ok(None)
+                }
+
+                fn visit_unit<E: Error>(self) -> Result<Option<T>, E> {
+                    Ok(None)
+                }
+
+                fn visit_some<D>(self, deserializer: D) -> Result<Option<T>, D::Error>
+                    where D: Deserializer<'de>
+                {
+                    super::deserialize(deserializer).map(Some)
+                }
+            }
+
+            deserializer.deserialize_option(OptionVisitor(::std::marker::PhantomData))
+        }
+    }
+}
+
+
+/// Primitives for the base64 variant used by bcrypt, with the alphabet
+/// `./A-Za-z0-9` and no padding.
+///
+/// `base64bcrypt` uses these for bcrypt's packed salt and hash; they are
+/// exposed for bcrypt-derived schemes with other layouts.
+pub mod bcrypt_base64 {
     use data_encoding::{Encoding, Specification};
+    use once_cell::race::OnceBox;
 
-    lazy_static! {
-        /// BCrypt-specific base64 encoding scheme.
-        static ref BASE64BCRYPT: Encoding = {
+    use errors::Result;
+    use prelude::*;
+
+    // BCrypt-specific base64 encoding scheme.
+    fn encoding() -> &'static Encoding {
+        static BASE64BCRYPT: OnceBox<Encoding> = OnceBox::new();
+        BASE64BCRYPT.get_or_init(|| {
             let mut spec = Specification::new();
             spec.symbols.push_str(
                 "./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789");
-            spec.encoding().unwrap()
-        };
+            Box::new(spec.encoding().unwrap())
+        })
     }
 
-    /// Custom deserialize method for `Bcrypt`.
+    /// Encode `bytes`, without padding.
+    pub fn encode(bytes: &[u8]) -> String {
+        encoding().encode(bytes)
+    }
+
+    /// Decode unpadded input.
+    pub fn decode(encoded: &str) -> Result<Vec<u8>> {
+        Ok(encoding().decode(encoded.as_bytes())?)
+    }
+
+    /// Split and decode a salt of `salt_len` bytes, encoded on its own,
+    /// followed by the encoded hash, as in bcrypt's 22-character salt and
+    /// 31-character hash.
+    pub fn split_salt_hash(encoded: &str, salt_len: usize) -> Result<(Vec<u8>, Vec<u8>)> {
+        // Each character holds 6 bits, and a partial final character is
+        // rounded up.
+        let split = (salt_len * 4).div_ceil(3);
+        if encoded.len() < split || !encoded.is_char_boundary(split) {
+            return Err("bcrypt salt and hash is too short".into());
+        }
+        Ok((decode(&encoded[..split])?, decode(&encoded[split..])?))
+    }
+}
+
+pub mod base64bcrypt {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use serde::ser::Error as SerError;
+
+    use prelude::*;
+    use super::bcrypt_base64;
+
+    /// Custom serialize method for `Bcrypt`.
+    ///
+    /// The salt must be 16 bytes, so that it encodes to the 22 characters
+    /// `deserialize` expects.
     pub fn serialize<T, S>(bytes: &(T, T), serializer: S) -> Result<S::Ok, S::Error>
         where T: AsRef<[u8]>,
               S: Serializer
     {
+        if bytes.0.as_ref().len() != 16 {
+            return Err(SerError::custom(format!("bcrypt salt must be 16 bytes, found {}",
+                                               bytes.0.as_ref().len())));
+        }
         serializer.serialize_str(
-            &(BASE64BCRYPT.encode(bytes.0.as_ref()) + 
-             &BASE64BCRYPT.encode(bytes.1.as_ref()))
+            &(bcrypt_base64::encode(bytes.0.as_ref()) +
+             &bcrypt_base64::encode(bytes.1.as_ref()))
         )
     }
 
@@ -57,10 +160,356 @@ pub mod base64bcrypt {
         where D: Deserializer<'de>
     {
         let encoded = String::deserialize(deserializer)?;
-        let (salt, hash) = (try!(BASE64BCRYPT.decode(&encoded.as_bytes()[..22])
-                                .map_err(|e| Error::custom(e.to_string()))),
-                            try!(BASE64BCRYPT.decode(&encoded.as_bytes()[22..])
-                                .map_err(|e| Error::custom(e.to_string()))));
-        Ok((salt, hash))
+        bcrypt_base64::split_salt_hash(&encoded, 16).map_err(|e| Error::custom(e.to_string()))
+    }
+}
+
+/// Helper methods for Passlib's adapted base64, which uses `.` in place of
+/// `+` and omits padding. Used by the Passlib `pbkdf2` hashes.
+pub mod ab64 {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    use data_encoding::{Encoding, Specification};
+    use once_cell::race::OnceBox;
+
+    use prelude::*;
+
+    /// The encoding itself, such as for decoding into an existing buffer.
+    pub fn encoding() -> &'static Encoding {
+        static AB64: OnceBox<Encoding> = OnceBox::new();
+        AB64.get_or_init(|| {
+            let mut spec = Specification::new();
+            spec.symbols.push_str(
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789./");
+            Box::new(spec.encoding().unwrap())
+        })
+    }
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: AsRef<[u8]>,
+              S: Serializer
+    {
+        serializer.serialize_str(&encoding().encode(bytes.as_ref()))
+    }
+
+    pub fn deserialize<'de, T: From<Vec<u8>>, D>(deserializer: D) -> Result<T, D::Error>
+        where D: Deserializer<'de>
+    {
+        let encoded = String::deserialize(deserializer)?;
+        encoding().decode(encoded.as_bytes())
+            .map(T::from)
+            .map_err(|e| Error::custom(e.to_string()))
+    }
+}
+
+// Shared implementation of the `crypt(3)`-style base64 used by the MD5 and
+// SHA-2 based crypt algorithms.
+//
+// Digest bytes are taken in groups (most significant first) according to a
+// per-algorithm permutation, and each group is encoded little-endian into
+// `group.len() + 1` characters of the `./0-9A-Za-z` alphabet.
+mod crypt64 {
+    use prelude::*;
+
+    const ALPHABET: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    pub fn encode(bytes: &[u8], order: &[&[usize]]) -> String {
+        let mut out = String::new();
+        for group in order {
+            let mut w = group.iter().fold(0u32, |acc, &i| (acc << 8) | u32::from(bytes[i]));
+            for _ in 0..group.len() + 1 {
+                out.push(ALPHABET[(w & 0x3f) as usize] as char);
+                w >>= 6;
+            }
+        }
+        out
+    }
+
+    pub fn decode(encoded: &str, order: &[&[usize]], len: usize) -> Result<Vec<u8>, String> {
+        let mut out = vec![0; len];
+        let mut chars = encoded.bytes();
+        for group in order {
+            let mut w = 0u32;
+            for k in 0..group.len() + 1 {
+                let c = chars.next().ok_or("encoded digest is too short")?;
+                let v = ALPHABET.iter()
+                    .position(|&a| a == c)
+                    .ok_or_else(|| format!("invalid character `{}` in digest", c as char))?;
+                w |= (v as u32) << (6 * k);
+            }
+            if w >> (8 * group.len()) != 0 {
+                return Err("non-canonical trailing bits in digest".to_string());
+            }
+            for (j, &i) in group.iter().enumerate() {
+                out[i] = (w >> (8 * (group.len() - 1 - j))) as u8;
+            }
+        }
+        if chars.next().is_some() {
+            return Err("encoded digest is too long".to_string());
+        }
+        Ok(out)
+    }
+}
+
+/// Helper methods for the digest encoding used by sha256crypt (`$5$`) and
+/// sha512crypt (`$6$`).
+///
+/// The variant is picked from the length: 32 bytes (43 characters) for
+/// sha256crypt, and 64 bytes (86 characters) for sha512crypt.
+pub mod sha2crypt {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use serde::ser::Error as SerError;
+
+    use prelude::*;
+    use super::crypt64;
+
+    const SHA256_ORDER: &[&[usize]] = &[
+        &[0, 10, 20], &[21, 1, 11], &[12, 22, 2], &[3, 13, 23], &[24, 4, 14],
+        &[15, 25, 5], &[6, 16, 26], &[27, 7, 17], &[18, 28, 8], &[9, 19, 29],
+        &[31, 30],
+    ];
+
+    const SHA512_ORDER: &[&[usize]] = &[
+        &[0, 21, 42], &[22, 43, 1], &[44, 2, 23], &[3, 24, 45], &[25, 46, 4],
+        &[47, 5, 26], &[6, 27, 48], &[28, 49, 7], &[50, 8, 29], &[9, 30, 51],
+        &[31, 52, 10], &[53, 11, 32], &[12, 33, 54], &[34, 55, 13], &[56, 14, 35],
+        &[15, 36, 57], &[37, 58, 16], &[59, 17, 38], &[18, 39, 60], &[40, 61, 19],
+        &[62, 20, 41], &[63],
+    ];
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: AsRef<[u8]>,
+              S: Serializer
+    {
+        let bytes = bytes.as_ref();
+        let order = match bytes.len() {
+            32 => SHA256_ORDER,
+            64 => SHA512_ORDER,
+            n => return Err(S::Error::custom(format!("invalid sha-crypt digest length {}", n))),
+        };
+        serializer.serialize_str(&crypt64::encode(bytes, order))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let encoded = String::deserialize(deserializer)?;
+        let (order, len) = match encoded.len() {
+            43 => (SHA256_ORDER, 32),
+            86 => (SHA512_ORDER, 64),
+            n => return Err(D::Error::custom(format!("invalid sha-crypt digest length {}", n))),
+        };
+        crypt64::decode(&encoded, order, len).map_err(D::Error::custom)
+    }
+}
+
+/// Helper methods for the digest encoding used by MD5-crypt (`$1$`) and
+/// Apache's variant of it (`$apr1$`).
+///
+/// The digest is always 16 bytes, encoded as 22 characters.
+pub mod md5crypt {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use serde::ser::Error as SerError;
+
+    use prelude::*;
+    use super::crypt64;
+
+    const MD5_ORDER: &[&[usize]] = &[
+        &[0, 6, 12], &[1, 7, 13], &[2, 8, 14], &[3, 9, 15], &[4, 10, 5], &[11],
+    ];
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: AsRef<[u8]>,
+              S: Serializer
+    {
+        let bytes = bytes.as_ref();
+        if bytes.len() != 16 {
+            return Err(S::Error::custom(format!("invalid md5-crypt digest length {}",
+                                                bytes.len())));
+        }
+        serializer.serialize_str(&crypt64::encode(bytes, MD5_ORDER))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let encoded = String::deserialize(deserializer)?;
+        crypt64::decode(&encoded, MD5_ORDER, 16).map_err(D::Error::custom)
+    }
+}
+
+/// Helper methods for the digest encoding used by yescrypt (`$y$`).
+///
+/// The digest is always 32 bytes, encoded little-endian as 43 characters.
+pub mod yescrypt {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+    use serde::ser::Error as SerError;
+
+    use prelude::*;
+    use super::crypt64;
+
+    const YESCRYPT_ORDER: &[&[usize]] = &[
+        &[2, 1, 0], &[5, 4, 3], &[8, 7, 6], &[11, 10, 9], &[14, 13, 12], &[17, 16, 15],
+        &[20, 19, 18], &[23, 22, 21], &[26, 25, 24], &[29, 28, 27], &[31, 30],
+    ];
+
+    pub fn serialize<T, S>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: AsRef<[u8]>,
+              S: Serializer
+    {
+        let bytes = bytes.as_ref();
+        if bytes.len() != 32 {
+            return Err(S::Error::custom(format!("invalid yescrypt digest length {}",
+                                                bytes.len())));
+        }
+        serializer.serialize_str(&crypt64::encode(bytes, YESCRYPT_ORDER))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+        where D: Deserializer<'de>
+    {
+        let encoded = String::deserialize(deserializer)?;
+        crypt64::decode(&encoded, YESCRYPT_ORDER, 32).map_err(D::Error::custom)
+    }
+}
+
+/// Base64 with an alphabet chosen at runtime, for formats using their own
+/// 64-character alphabet.
+///
+/// Use it as a field adapter by calling `serialize`/`deserialize` from the
+/// functions named in `#[serde(serialize_with, deserialize_with)]`.
+#[derive(Clone, Debug)]
+pub struct Base64Custom {
+    encoding: Encoding,
+}
+
+impl Base64Custom {
+    /// Create an unpadded base64 encoding using `alphabet`, which must be 64
+    /// unique ASCII characters.
+    pub fn new(alphabet: &str) -> ::errors::Result<Base64Custom> {
+        if alphabet.len() != 64 || !alphabet.is_ascii() {
+            return Err("a base64 alphabet must be 64 ASCII characters".into());
+        }
+        let mut spec = Specification::new();
+        spec.symbols.push_str(alphabet);
+        let encoding = spec.encoding().map_err(|e| format!("invalid base64 alphabet: {}", e))?;
+        Ok(Base64Custom { encoding })
+    }
+
+    /// Encode `bytes` as a string.
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        self.encoding.encode(bytes)
+    }
+
+    /// Decode a string to bytes.
+    pub fn decode(&self, encoded: &str) -> ::errors::Result<Vec<u8>> {
+        self.encoding.decode(encoded.as_bytes()).map_err(|e| e.into())
+    }
+
+    pub fn serialize<T, S>(&self, bytes: &T, serializer: S) -> Result<S::Ok, S::Error>
+        where T: AsRef<[u8]>,
+              S: Serializer
+    {
+        serializer.serialize_str(&self.encode(bytes.as_ref()))
+    }
+
+    pub fn deserialize<'de, T: From<Vec<u8>>, D>(&self, deserializer: D) -> Result<T, D::Error>
+        where D: Deserializer<'de>
+    {
+        let encoded = String::deserialize(deserializer)?;
+        self.encoding
+            .decode(encoded.as_bytes())
+            .map(T::from)
+            .map_err(|e| de::Error::custom(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {from_str, to_string};
+
+    lazy_static! {
+        static ref SHUFFLED: Base64Custom = Base64Custom::new(
+            "zyxwvutsrqponmlkjihgfedcbaZYXWVUTSRQPONMLKJIHGFEDCBA9876543210-_").unwrap();
+    }
+
+    #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    struct TestStruct {
+        p: u8,
+        #[serde(serialize_with = "ser_shuffled", deserialize_with = "de_shuffled")]
+        hash: Vec<u8>,
+    }
+
+    fn ser_shuffled<S: Serializer>(bytes: &Vec<u8>, s: S) -> Result<S::Ok, S::Error> {
+        SHUFFLED.serialize(bytes, s)
+    }
+
+    fn de_shuffled<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        SHUFFLED.deserialize(d)
+    }
+
+    #[test]
+    fn test_custom_alphabet() {
+        let t = TestStruct {
+            p: 1,
+            hash: b"hello".to_vec(),
+        };
+        let encoded = to_string(&t).unwrap();
+        assert_eq!(encoded, format!("$1${}", SHUFFLED.encode(b"hello")));
+        assert!(encoded != "$1$aGVsbG8");
+        assert_eq!(from_str::<TestStruct>(&encoded).unwrap(), t);
+
+        let duplicate = "aaxwvutsrqponmlkjihgfedcbaZYXWVUTSRQPONMLKJIHGFEDCBA9876543210-_";
+        assert!(Base64Custom::new(duplicate).is_err());
+        assert!(Base64Custom::new("abc").is_err());
+    }
+
+    #[test]
+    fn test_bcrypt_salt_length() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Bcrypt {
+            cost: u8,
+            #[serde(with = "base64bcrypt")]
+            salthash: (Vec<u8>, Vec<u8>),
+        }
+
+        let valid = Bcrypt {
+            cost: 10,
+            salthash: (vec![0; 16], vec![1; 23]),
+        };
+        let encoded = to_string(&valid).unwrap();
+        assert_eq!(from_str::<Bcrypt>(&encoded).unwrap(), valid);
+
+        let short = Bcrypt {
+            cost: 10,
+            salthash: (vec![0; 15], vec![1; 23]),
+        };
+        assert!(to_string(&short).is_err());
+        assert!(from_str::<Bcrypt>("$10$abc").is_err());
+    }
+
+    #[test]
+    fn test_bcrypt_base64() {
+        assert_eq!(bcrypt_base64::encode(&[0; 3]), "....");
+        assert_eq!(bcrypt_base64::encode(&[0xff; 3]), "9999");
+        assert_eq!(bcrypt_base64::encode(b"somesalt"), "a07rXVLfZFO");
+        assert_eq!(bcrypt_base64::decode("a07rXVLfZFO").unwrap(), b"somesalt");
+        assert!(bcrypt_base64::decode("a07rXVLfZF+").is_err());
+
+        // An 8-byte salt takes 11 characters.
+        let encoded = bcrypt_base64::encode(b"somesalt") + &bcrypt_base64::encode(b"hash");
+        let (salt, hash) = bcrypt_base64::split_salt_hash(&encoded, 8).unwrap();
+        assert_eq!(salt, b"somesalt");
+        assert_eq!(hash, b"hash");
+        assert!(bcrypt_base64::split_salt_hash("a07rXVLfZF", 8).is_err());
+
+        let (salt, hash) = bcrypt_base64::split_salt_hash("ckjEeyTD6estWyoofn4ERO\
+            M9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe", 16).unwrap();
+        assert_eq!((salt.len(), hash.len()), (16, 23));
     }
 }
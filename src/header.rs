@@ -0,0 +1,144 @@
+//! A variant of the MCF string which is safe to use as an HTTP header or
+//! cookie value.
+//!
+//! The salt and hash use URL-safe base64. Parameters are written as
+//! `key=value` entries separated by `&`, since `,` is not allowed in a cookie
+//! value (RFC 6265 `cookie-octet`). Within keys and values, any character
+//! which is not allowed is percent-encoded, along with `%`, `$`, `=` and `&`
+//! themselves.
+
+use data_encoding::BASE64URL_NOPAD;
+
+use errors::*;
+use prelude::*;
+use de::parse_parts;
+use phc::value_to_string;
+use super::{Hashes, Map, McfHash, Value};
+
+// Whether `b` can appear unescaped in a parameter key or value.
+fn is_header_safe(b: u8) -> bool {
+    match b {
+        b'"' | b',' | b';' | b'\\' | b'%' | b'$' | b'=' | b'&' => false,
+        0x21..=0x7e => true,
+        _ => false,
+    }
+}
+
+fn escape(input: &str, out: &mut String) {
+    for &b in input.as_bytes() {
+        if is_header_safe(b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+}
+
+fn unescape(input: &str) -> Result<String> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut bytes = input.bytes();
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+        let hex = [bytes.next(), bytes.next()];
+        let byte = match hex {
+            [Some(h), Some(l)] => {
+                let hex = [h, l];
+                ::std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|s| u8::from_str_radix(s, 16).ok())
+            }
+            _ => None,
+        };
+        out.push(byte.ok_or("invalid percent-encoding in parameters")?);
+    }
+    String::from_utf8(out).map_err(|_| "parameters are not valid UTF-8".into())
+}
+
+impl McfHash {
+    /// Format the hash for use as an HTTP header or cookie value.
+    ///
+    /// The result uses the same fields as `to_string`, but with URL-safe
+    /// base64 and the parameter field percent-encoded as needed. Use
+    /// `from_header_safe_str` to parse it.
+    pub fn to_header_safe_string(&self) -> String {
+        let mut out = format!("${}$", self.algorithm.to_id());
+        for (i, (key, value)) in self.parameters.iter().enumerate() {
+            if i > 0 {
+                out.push('&');
+            }
+            escape(key, &mut out);
+            out.push('=');
+            escape(&value_to_string(value), &mut out);
+        }
+        if let Some(ref salt) = self.salt {
+            out.push('$');
+            out.push_str(&BASE64URL_NOPAD.encode(salt));
+        }
+        out.push('$');
+        out.push_str(&BASE64URL_NOPAD.encode(&self.hash));
+        out
+    }
+
+    /// Parse a string produced by `to_header_safe_string`.
+    ///
+    /// Parameter values are kept as strings, matching `from_str`.
+    pub fn from_header_safe_str(input: &str) -> Result<McfHash> {
+        let parts = parse_parts(input)?;
+        let algorithm = Hashes::from_id(parts.algorithm)
+            .ok_or_else(|| format!("unknown algorithm identifier `{}`", parts.algorithm))?;
+        let mut parameters = Map::new();
+        if !parts.params.is_empty() {
+            for param in parts.params.split('&') {
+                let mut kv = param.splitn(2, '=');
+                let key = unescape(kv.next().unwrap())?;
+                let value = kv.next().ok_or_else(|| format!("parameter `{}` has no value", key))?;
+                parameters.insert(key, Value::String(unescape(value)?));
+            }
+        }
+        let salt = match parts.salt {
+            Some(s) => Some(BASE64URL_NOPAD.decode(s.as_bytes())?),
+            None => None,
+        };
+        Ok(McfHash {
+            algorithm,
+            parameters,
+            salt,
+            hash: BASE64URL_NOPAD.decode(parts.hash.as_bytes())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use from_str;
+
+    #[test]
+    fn test_header_safe_roundtrip() {
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let mut hash: McfHash = from_str(argon_hash).unwrap();
+        hash.parameters.insert("data".to_string(), Value::String("a b;\"c\"\\%".to_string()));
+        hash.parameters.insert("list".to_string(), Value::String("a,b=c&d".to_string()));
+        hash.parameters.insert("k=&".to_string(), Value::String(String::new()));
+
+        let header = hash.to_header_safe_string();
+        let cookie_octet = |b: u8| b == 0x21 || (0x23..=0x7e).contains(&b) && b != b',' &&
+                                                 b != b';' && b != b'\\';
+        assert!(header.bytes().all(cookie_octet));
+        assert!(header.contains("$m=262144&p=1&t=2&data="));
+        assert!(header.contains("&list=a%2Cb%3Dc%26d&k%3D%26="));
+        assert!(header.ends_with("$c29tZXNhbHQ$Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4_Z3p9pMJGc"));
+        assert_eq!(McfHash::from_header_safe_str(&header).unwrap(), hash);
+
+        let nthash: McfHash = from_str("$3$$iEb36u6PsRetBr3YMLdYbA").unwrap();
+        let header = nthash.to_header_safe_string();
+        assert_eq!(header, "$3$$iEb36u6PsRetBr3YMLdYbA");
+        assert_eq!(McfHash::from_header_safe_str(&header).unwrap(), nthash);
+
+        assert!(McfHash::from_header_safe_str("$argon2i$m=1%2$c2FsdA$aGFzaA").is_err());
+    }
+}
@@ -0,0 +1,1056 @@
+//! Algorithm-specific hash layouts which don't fit the generic `McfHash`.
+
+use super::*;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use data_encoding::{Encoding, Specification, BASE64_NOPAD};
+use errors::{Error, Result};
+use once_cell::race::OnceBox;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// MCF style `Bcrypt` hash
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BcryptHash {
+    algorithm: Hashes,
+    cost: u8,
+    #[serde(with = "base64bcrypt")]
+    salthash: (Vec<u8>, Vec<u8>),
+}
+
+impl From<BcryptHash> for McfHash {
+    fn from(hash: BcryptHash) -> McfHash {
+        let mut params = Map::<String, Value>::new();
+        params.insert("cost".to_string(), Value::Number(hash.cost.into()));
+        McfHash {
+            algorithm: hash.algorithm,
+            parameters: params,
+            salt: Some(hash.salthash.0),
+            hash: hash.salthash.1,
+        }
+    }
+}
+
+impl TryFrom<McfHash> for BcryptHash {
+    type Error = Error;
+
+    /// Convert a generic hash back into the compact bcrypt layout.
+    ///
+    /// Fails unless the algorithm is a bcrypt variant with a `cost`
+    /// parameter and a salt.
+    fn try_from(mut hash: McfHash) -> Result<BcryptHash> {
+        if !hash.algorithm.is_bcrypt() {
+            return Err(format!("expected a bcrypt hash, found `{}`", hash.algorithm.to_id())
+                .into());
+        }
+        let cost = hash.param_u64("cost").ok_or("missing bcrypt parameter `cost`")?;
+        let cost = u8::try_from(cost).map_err(|_| format!("invalid bcrypt cost `{}`", cost))?;
+        let salt = hash.salt.take().ok_or("missing bcrypt salt")?;
+        Ok(BcryptHash {
+            algorithm: hash.algorithm.clone(),
+            cost,
+            salthash: (salt, ::std::mem::take(&mut hash.hash)),
+        })
+    }
+}
+
+/// libpasta's `$2y-mcf$` bcrypt hash, such as `$2y-mcf$cost=10$salt$hash`.
+///
+/// Unlike `BcryptHash`, the salt and hash are separate fields in standard
+/// base64, as for other MCF hashes. The cost may also be given bare, as in
+/// `$2y-mcf$10$salt$hash`, but is always written as `cost=N`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BcryptMcfHash {
+    pub algorithm: Hashes,
+    #[serde(serialize_with = "serialize_cost")]
+    pub cost: u8,
+    #[serde(with = "base64")]
+    pub salt: Vec<u8>,
+    #[serde(with = "base64")]
+    pub hash: Vec<u8>,
+}
+
+fn serialize_cost<S>(cost: &u8, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    serializer.serialize_str(&format!("cost={}", cost))
+}
+
+impl<'de> Deserialize<'de> for BcryptMcfHash {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<BcryptMcfHash, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        #[serde(rename = "BcryptMcfHash")]
+        struct RawBcryptMcfHash {
+            algorithm: Hashes,
+            cost: String,
+            #[serde(with = "base64")]
+            salt: Vec<u8>,
+            #[serde(with = "base64")]
+            hash: Vec<u8>,
+        }
+
+        let raw = RawBcryptMcfHash::deserialize(deserializer)?;
+        if raw.algorithm != Hashes::BcryptMcf {
+            return Err(de::Error::custom(format!("expected `2y-mcf`, found `{}`",
+                                                 raw.algorithm.to_id())));
+        }
+        let cost = raw.cost
+            .strip_prefix("cost=")
+            .unwrap_or(&raw.cost)
+            .parse()
+            .map_err(|_| de::Error::custom(format!("invalid bcrypt cost `{}`", raw.cost)))?;
+        if raw.salt.len() != 16 || raw.hash.len() != 23 {
+            return Err(de::Error::custom(format!("bcrypt salt and hash must be 16 and 23 \
+                                                  bytes, found {} and {}",
+                                                 raw.salt.len(),
+                                                 raw.hash.len())));
+        }
+        Ok(BcryptMcfHash {
+            algorithm: raw.algorithm,
+            cost,
+            salt: raw.salt,
+            hash: raw.hash,
+        })
+    }
+}
+
+impl From<BcryptMcfHash> for McfHash {
+    fn from(hash: BcryptMcfHash) -> McfHash {
+        let mut params = Map::<String, Value>::new();
+        params.insert("cost".to_string(), Value::Number(hash.cost.into()));
+        McfHash {
+            algorithm: hash.algorithm,
+            parameters: params,
+            salt: Some(hash.salt),
+            hash: hash.hash,
+        }
+    }
+}
+
+/// Passlib's `bcrypt-sha256` hash, which applies bcrypt to the HMAC-SHA256 of
+/// the password.
+///
+/// Version 2 hashes have the form `$bcrypt-sha256$v=2,t=2b,r=12$salt$hash`,
+/// where `t` is the bcrypt variant and `r` the cost. Version 1 hashes have
+/// the form `$bcrypt-sha256$2a,12$salt$hash`. Both are accepted, and written
+/// back in the form given by `version`. The salt and hash use bcrypt's
+/// base64, and are 16 and 23 bytes as for bcrypt.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BcryptSha256Hash {
+    /// The Passlib format version, 1 or 2.
+    pub version: u8,
+    /// The bcrypt variant, such as `Hashes::Bcryptb`.
+    pub variant: Hashes,
+    pub cost: u8,
+    pub salt: Vec<u8>,
+    pub hash: Vec<u8>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename = "BcryptSha256Hash")]
+struct RawBcryptSha256Hash {
+    algorithm: Hashes,
+    params: String,
+    salt: String,
+    hash: String,
+}
+
+impl BcryptSha256Hash {
+    // Parse the version, variant and cost from the parameters field.
+    fn parse_params(field: &str) -> Result<(u8, Hashes, u8)> {
+        let parse_cost = |cost: &str| {
+            cost.parse().map_err(|_| Error::from(format!("invalid bcrypt cost `{}`", cost)))
+        };
+        if !field.contains('=') {
+            let mut parts = field.splitn(2, ',');
+            let variant = Hashes::try_from(parts.next().unwrap())?;
+            let cost = parts.next().ok_or("missing bcrypt-sha256 cost")?;
+            return Ok((1, variant, parse_cost(cost)?));
+        }
+        let (mut version, mut variant, mut cost) = (None, None, None);
+        for param in field.split(',') {
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next().unwrap();
+            let value = kv.next().ok_or_else(|| format!("parameter `{}` has no value", key))?;
+            match key {
+                "v" => {
+                    version = Some(value.parse()
+                        .map_err(|_| format!("invalid bcrypt-sha256 version `{}`", value))?)
+                }
+                "t" => variant = Some(Hashes::try_from(value)?),
+                "r" => cost = Some(parse_cost(value)?),
+                _ => return Err(format!("unknown bcrypt-sha256 parameter `{}`", key).into()),
+            }
+        }
+        Ok((version.ok_or("missing bcrypt-sha256 parameter `v`")?,
+            variant.ok_or("missing bcrypt-sha256 parameter `t`")?,
+            cost.ok_or("missing bcrypt-sha256 parameter `r`")?))
+    }
+}
+
+impl<'de> Deserialize<'de> for BcryptSha256Hash {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<BcryptSha256Hash, D::Error>
+        where D: Deserializer<'de>
+    {
+        let raw = RawBcryptSha256Hash::deserialize(deserializer)?;
+        if raw.algorithm != Hashes::BcryptSha256 {
+            return Err(de::Error::custom(format!("expected `bcrypt-sha256`, found `{}`",
+                                                 raw.algorithm.to_id())));
+        }
+        let (version, variant, cost) = BcryptSha256Hash::parse_params(&raw.params)
+            .map_err(de::Error::custom)?;
+        if !variant.is_bcrypt() || !(version == 1 || version == 2) {
+            return Err(de::Error::custom(format!("unsupported bcrypt-sha256 parameters `{}`",
+                                                 raw.params)));
+        }
+        let salt = encoding::bcrypt_base64::decode(&raw.salt).map_err(de::Error::custom)?;
+        let hash = encoding::bcrypt_base64::decode(&raw.hash).map_err(de::Error::custom)?;
+        if salt.len() != 16 || hash.len() != 23 {
+            return Err(de::Error::custom(format!("bcrypt salt and hash must be 16 and 23 \
+                                                  bytes, found {} and {}",
+                                                 salt.len(),
+                                                 hash.len())));
+        }
+        Ok(BcryptSha256Hash {
+            version,
+            variant,
+            cost,
+            salt,
+            hash,
+        })
+    }
+}
+
+impl Serialize for BcryptSha256Hash {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let params = if self.version == 1 {
+            format!("{},{}", self.variant.to_id(), self.cost)
+        } else {
+            format!("v={},t={},r={}", self.version, self.variant.to_id(), self.cost)
+        };
+        RawBcryptSha256Hash {
+                algorithm: Hashes::BcryptSha256,
+                params,
+                salt: encoding::bcrypt_base64::encode(&self.salt),
+                hash: encoding::bcrypt_base64::encode(&self.hash),
+            }
+            .serialize(serializer)
+    }
+}
+
+/// Passlib-style `scrypt` hash.
+///
+/// The parameters are either in the map form `ln=16,r=8,p=1`, or packed into a
+/// single base64 blob. Both forms are accepted, and re-serialized as found.
+///
+/// Passlib only writes the map form, and we know of no producer of the
+/// packed form, so its support is only tested against hashes encoded by this
+/// crate. It is not the `$7$` format of the scrypt reference implementation,
+/// which packs the parameters differently.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ScryptHash {
+    pub algorithm: Hashes,
+    pub params: ScryptParams,
+    #[serde(with = "base64")]
+    pub salt: Vec<u8>,
+    #[serde(with = "base64")]
+    pub hash: Vec<u8>,
+}
+
+/// The `scrypt` cost parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScryptParams {
+    /// Log2 of the CPU/memory cost `N`.
+    pub ln: u8,
+    /// Block size.
+    pub r: u32,
+    /// Parallelism.
+    pub p: u32,
+    /// Whether the parameters use the packed encoding: the base64 of `ln` as
+    /// a single byte, followed by `r` and `p` as big-endian `u32`s.
+    pub packed: bool,
+    // The order of the keys in the map form, to write them back as found.
+    order: [&'static str; 3],
+}
+
+const SCRYPT_KEYS: [&str; 3] = ["ln", "r", "p"];
+
+impl ScryptParams {
+    /// The CPU/memory cost `N`, which is `2^ln`, or `None` if it does not
+    /// fit in a `u64`.
+    pub fn n(&self) -> Option<u64> {
+        1u64.checked_shl(u32::from(self.ln))
+    }
+
+    fn from_packed(field: &str) -> Result<ScryptParams> {
+        let bytes = BASE64_NOPAD.decode(field.as_bytes())?;
+        if bytes.len() != 9 {
+            return Err(format!("packed scrypt parameters must be 9 bytes, found {}",
+                               bytes.len())
+                .into());
+        }
+        let be_u32 = |b: &[u8]| b.iter().fold(0u32, |acc, &x| (acc << 8) | u32::from(x));
+        Ok(ScryptParams {
+            ln: bytes[0],
+            r: be_u32(&bytes[1..5]),
+            p: be_u32(&bytes[5..9]),
+            packed: true,
+            order: SCRYPT_KEYS,
+        })
+    }
+
+    fn from_map(field: &str) -> Result<ScryptParams> {
+        let (mut ln, mut r, mut p) = (None, None, None);
+        let mut order = Vec::with_capacity(3);
+        for param in field.split(',') {
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next().unwrap();
+            let value = kv.next().ok_or_else(|| format!("parameter `{}` has no value", key))?;
+            match key {
+                "ln" => ln = Some(value.parse().map_err(|e| format!("invalid `ln`: {}", e))?),
+                "r" => r = Some(value.parse().map_err(|e| format!("invalid `r`: {}", e))?),
+                "p" => p = Some(value.parse().map_err(|e| format!("invalid `p`: {}", e))?),
+                _ => return Err(format!("unknown scrypt parameter `{}`", key).into()),
+            }
+            let key = SCRYPT_KEYS.iter().find(|&&k| k == key).unwrap();
+            if order.contains(key) {
+                return Err(format!("duplicate scrypt parameter `{}`", key).into());
+            }
+            order.push(*key);
+        }
+        Ok(ScryptParams {
+            ln: ln.ok_or("missing scrypt parameter `ln`")?,
+            r: r.ok_or("missing scrypt parameter `r`")?,
+            p: p.ok_or("missing scrypt parameter `p`")?,
+            packed: false,
+            order: [order[0], order[1], order[2]],
+        })
+    }
+
+    // The map form's values, in the order they were read.
+    fn entries(&self) -> Vec<(&'static str, u32)> {
+        self.order
+            .iter()
+            .map(|&key| match key {
+                "ln" => (key, u32::from(self.ln)),
+                "r" => (key, self.r),
+                _ => (key, self.p),
+            })
+            .collect()
+    }
+}
+
+impl<'de> Deserialize<'de> for ScryptParams {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<ScryptParams, D::Error>
+        where D: Deserializer<'de>
+    {
+        let field = String::deserialize(deserializer)?;
+        let params = if field.contains('=') {
+            ScryptParams::from_map(&field)
+        } else {
+            ScryptParams::from_packed(&field)
+        };
+        params.map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for ScryptParams {
+    fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        if self.packed {
+            let mut bytes = vec![self.ln];
+            for v in &[self.r, self.p] {
+                bytes.extend_from_slice(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, *v as u8]);
+            }
+            serializer.serialize_str(&BASE64_NOPAD.encode(&bytes))
+        } else {
+            let entries = self.entries()
+                .iter()
+                .map(|&(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>();
+            serializer.serialize_str(&entries.join(","))
+        }
+    }
+}
+
+impl From<ScryptHash> for McfHash {
+    fn from(hash: ScryptHash) -> McfHash {
+        let params = hash.params
+            .entries()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Value::Number(value.into())))
+            .collect();
+        McfHash {
+            algorithm: hash.algorithm,
+            parameters: params,
+            salt: Some(hash.salt),
+            hash: hash.hash,
+        }
+    }
+}
+
+impl McfHash {
+    /// Read the parameters of an `scrypt` hash.
+    ///
+    /// The cost may be given either as `ln`, or as `N` directly, in which
+    /// case it must be a power of two. Returns `None` for other algorithms, or
+    /// if a parameter is missing or out of range.
+    pub fn scrypt_params(&self) -> Option<ScryptParams> {
+        if self.algorithm != Hashes::Scrypt {
+            return None;
+        }
+        // Keep the order of the parameters, with `N` standing in for `ln`.
+        let mut order = Vec::with_capacity(3);
+        for key in self.parameters.keys() {
+            let key = if key == "N" { "ln" } else { key.as_str() };
+            if let Some(key) = SCRYPT_KEYS.iter().find(|&&k| k == key) {
+                if !order.contains(key) {
+                    order.push(*key);
+                }
+            }
+        }
+        let ln = match self.param_u64("ln") {
+            Some(ln) if ln < 64 => ln as u8,
+            Some(_) => return None,
+            None => {
+                let n = self.param_u64("N")?;
+                if !n.is_power_of_two() {
+                    return None;
+                }
+                n.trailing_zeros() as u8
+            }
+        };
+        Some(ScryptParams {
+            ln,
+            r: self.param_u64("r").and_then(|r| u32::try_from(r).ok())?,
+            p: self.param_u64("p").and_then(|p| u32::try_from(p).ok())?,
+            packed: false,
+            order: [order[0], order[1], order[2]],
+        })
+    }
+}
+
+/// `sha256crypt` or `sha512crypt` hash, such as `$6$rounds=5000$salt$hash`.
+///
+/// The salt is used verbatim by these algorithms, so it is kept as a string,
+/// while the digest uses the `sha2crypt` encoding.
+///
+/// The `rounds=N` field is optional, and is omitted for the default of 5000
+/// rounds, as in `$6$salt$hash`. When present, it is the only entry of
+/// `parameters`; otherwise `parameters` is empty, and so is not serialized.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ShaCryptHash {
+    pub algorithm: Hashes,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub parameters: Map<String, Value>,
+    pub salt: String,
+    #[serde(with = "sha2crypt")]
+    pub hash: Vec<u8>,
+}
+
+impl ShaCryptHash {
+    /// The number of rounds, taking the default into account.
+    pub fn rounds(&self) -> u64 {
+        self.parameters.get("rounds").and_then(Value::as_u64).unwrap_or(5000)
+    }
+}
+
+impl<'de> Deserialize<'de> for ShaCryptHash {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<ShaCryptHash, D::Error>
+        where D: Deserializer<'de>
+    {
+        // Without a rounds field, the salt is the second field and the hash
+        // the third.
+        #[derive(Deserialize)]
+        #[serde(rename = "ShaCryptHash")]
+        struct RawShaCryptHash {
+            algorithm: Hashes,
+            first: String,
+            second: String,
+            #[serde(default)]
+            third: Option<String>,
+        }
+
+        let RawShaCryptHash { algorithm, first, second, third } =
+            RawShaCryptHash::deserialize(deserializer)?;
+        let mut parameters = Map::new();
+        let (salt, hash) = match third {
+            Some(hash) => {
+                let rounds = first.strip_prefix("rounds=")
+                    .and_then(|r| r.parse::<u32>().ok())
+                    .ok_or_else(|| {
+                        de::Error::custom(format!("expected `rounds=N`, found `{}`", first))
+                    })?;
+                parameters.insert("rounds".to_string(), Value::Number(rounds.into()));
+                (second, hash)
+            }
+            None => (first, second),
+        };
+        Ok(ShaCryptHash {
+            algorithm,
+            parameters,
+            salt,
+            hash: sha2crypt::deserialize(de::IntoDeserializer::into_deserializer(hash))?,
+        })
+    }
+}
+
+/// `md5crypt` hash, either `$1$salt$hash` or Apache's `$apr1$salt$hash`.
+///
+/// As with sha-crypt, the salt is used verbatim, while the digest uses the
+/// `md5crypt` encoding.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Md5CryptHash {
+    pub algorithm: Hashes,
+    pub salt: String,
+    #[serde(with = "md5crypt")]
+    pub hash: Vec<u8>,
+}
+
+/// Passlib-style `pbkdf2` hash, such as `$pbkdf2-sha256$29000$salt$hash`.
+///
+/// The salt and digest use Passlib's adapted base64. On deserializing, the
+/// digest length is checked against the variant: 20 bytes for `pbkdf2`
+/// (SHA-1), 32 for `pbkdf2-sha256` and 64 for `pbkdf2-sha512`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Pbkdf2Hash {
+    pub algorithm: Hashes,
+    pub rounds: u32,
+    #[serde(with = "encoding::ab64")]
+    pub salt: Vec<u8>,
+    #[serde(with = "encoding::ab64")]
+    pub hash: Vec<u8>,
+}
+
+impl<'de> Deserialize<'de> for Pbkdf2Hash {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Pbkdf2Hash, D::Error>
+        where D: Deserializer<'de>
+    {
+        #[derive(Deserialize)]
+        #[serde(rename = "Pbkdf2Hash")]
+        struct RawPbkdf2Hash {
+            algorithm: Hashes,
+            rounds: u32,
+            #[serde(with = "encoding::ab64")]
+            salt: Vec<u8>,
+            #[serde(with = "encoding::ab64")]
+            hash: Vec<u8>,
+        }
+
+        let raw = RawPbkdf2Hash::deserialize(deserializer)?;
+        let digest_len = match raw.algorithm {
+            Hashes::Pbkdf2Sha1 => 20,
+            Hashes::Pbkdf2Sha256 => 32,
+            Hashes::Pbkdf2Sha512 => 64,
+            ref alg => {
+                return Err(de::Error::custom(format!("`{}` is not a pbkdf2 algorithm",
+                                                     alg.to_id())))
+            }
+        };
+        if raw.hash.len() != digest_len {
+            return Err(de::Error::custom(format!("`{}` digest must be {} bytes, found {}",
+                                                 raw.algorithm.to_id(),
+                                                 digest_len,
+                                                 raw.hash.len())));
+        }
+        Ok(Pbkdf2Hash {
+            algorithm: raw.algorithm,
+            rounds: raw.rounds,
+            salt: raw.salt,
+            hash: raw.hash,
+        })
+    }
+}
+
+impl From<Pbkdf2Hash> for McfHash {
+    fn from(hash: Pbkdf2Hash) -> McfHash {
+        let mut params = Map::<String, Value>::new();
+        params.insert("rounds".to_string(), Value::Number(hash.rounds.into()));
+        McfHash {
+            algorithm: hash.algorithm,
+            parameters: params,
+            salt: Some(hash.salt),
+            hash: hash.hash,
+        }
+    }
+}
+
+/// Layout of a single field holding both the salt and the hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CombinedSaltHash {
+    /// The salt comes first and is this many bytes long, followed by the
+    /// hash, as in bcrypt.
+    SaltFirst(usize),
+    /// The hash comes first and is this many bytes long, followed by the
+    /// salt, as in LDAP's `{SSHA}`.
+    HashFirst(usize),
+}
+
+/// Parse a hash of the form `$alg$params$salthash`, where the salt and hash
+/// are concatenated and base64 encoded as a single field, and split them
+/// according to `layout`.
+///
+/// Note bcrypt itself encodes the salt and hash separately with its own
+/// alphabet, which `BcryptHash` handles; this is for formats which encode
+/// the concatenated bytes in one go.
+pub fn from_str_combined(input: &str, layout: CombinedSaltHash) -> Result<McfHash> {
+    let mut hash: McfHash = from_str(input)?;
+    if hash.salt.is_some() {
+        return Err("expected the salt and hash in a single field".into());
+    }
+    let combined = ::std::mem::take(&mut hash.hash);
+    let at = match layout {
+        CombinedSaltHash::SaltFirst(len) | CombinedSaltHash::HashFirst(len) => len,
+    };
+    if combined.len() < at {
+        return Err(format!("combined field is {} bytes, but needs at least {}",
+                           combined.len(),
+                           at)
+            .into());
+    }
+    let (first, second) = combined.split_at(at);
+    let (salt, digest) = match layout {
+        CombinedSaltHash::SaltFirst(_) => (first, second),
+        CombinedSaltHash::HashFirst(_) => (second, first),
+    };
+    hash.salt = Some(salt.to_vec());
+    hash.hash = digest.to_vec();
+    Ok(hash)
+}
+
+const HASH64_ALPHABET: &str = "./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+// The big-endian base64 variant used by traditional DES `crypt(3)`.
+fn des_hash64() -> &'static Encoding {
+    static DES_HASH64: OnceBox<Encoding> = OnceBox::new();
+    DES_HASH64.get_or_init(|| {
+        let mut spec = Specification::new();
+        spec.symbols.push_str(HASH64_ALPHABET);
+        Box::new(spec.encoding().unwrap())
+    })
+}
+
+/// Traditional DES `crypt(3)` hash, such as `abJnggxhB/yWI`.
+///
+/// These have no `$` prefix and are 13 characters long: a 2-character salt
+/// followed by the 11-character hash. Parse them with `str::parse` and write
+/// them with `to_string`. As an `McfHash`, they have the `DesCrypt`
+/// algorithm, the salt as two little-endian bytes, and the hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DesCrypt {
+    /// The 12-bit salt.
+    pub salt: u16,
+    /// The 8-byte hash.
+    pub hash: [u8; 8],
+}
+
+impl FromStr for DesCrypt {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<DesCrypt> {
+        if input.len() != 13 || !input.is_ascii() {
+            return Err("not a DES crypt hash".into());
+        }
+        let salt = input[..2]
+            .chars()
+            .map(|c| {
+                HASH64_ALPHABET.find(c).ok_or_else(|| format!("invalid salt character `{}`", c))
+            })
+            .collect::<::std::result::Result<Vec<usize>, _>>()?;
+        let mut hash = [0; 8];
+        des_hash64().decode_mut(&input.as_bytes()[2..], &mut hash).map_err(|e| e.error)?;
+        Ok(DesCrypt {
+            salt: (salt[0] | (salt[1] << 6)) as u16,
+            hash,
+        })
+    }
+}
+
+impl fmt::Display for DesCrypt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &v in &[self.salt & 0x3f, (self.salt >> 6) & 0x3f] {
+            write!(f, "{}", HASH64_ALPHABET.as_bytes()[v as usize] as char)?;
+        }
+        f.write_str(&des_hash64().encode(&self.hash))
+    }
+}
+
+impl From<DesCrypt> for McfHash {
+    fn from(hash: DesCrypt) -> McfHash {
+        McfHash {
+            algorithm: Hashes::DesCrypt,
+            parameters: Map::new(),
+            salt: Some(hash.salt.to_le_bytes().to_vec()),
+            hash: hash.hash.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<McfHash> for DesCrypt {
+    type Error = Error;
+
+    /// Convert a generic hash back into a DES crypt hash.
+    ///
+    /// Fails unless the algorithm is `DesCrypt`, with a 12-bit salt and an
+    /// 8-byte hash.
+    fn try_from(hash: McfHash) -> Result<DesCrypt> {
+        if hash.algorithm != Hashes::DesCrypt {
+            return Err(format!("expected a DES crypt hash, found `{}`", hash.algorithm.to_id())
+                .into());
+        }
+        match (hash.salt.as_deref(), <[u8; 8]>::try_from(&hash.hash[..])) {
+            (Some(&[low, high]), Ok(digest)) if high <= 0x0f => {
+                Ok(DesCrypt {
+                    salt: u16::from_le_bytes([low, high]),
+                    hash: digest,
+                })
+            }
+            _ => Err("DES crypt needs a 12-bit salt and an 8-byte hash".into()),
+        }
+    }
+}
+
+/// Parse either an MCF hash, or a traditional DES `crypt(3)` hash.
+///
+/// DES hashes are read as `DesCrypt`, and converted to an `McfHash`.
+pub fn from_crypt_str(input: &str) -> Result<McfHash> {
+    if input.starts_with('$') {
+        return from_str(input);
+    }
+    match input.parse::<DesCrypt>() {
+        Ok(hash) => Ok(hash.into()),
+        Err(_) if input.len() != 13 => Err("not an MCF or DES crypt hash".into()),
+        Err(e) => Err(e),
+    }
+}
+
+impl McfHash {
+    /// Format a `DesCrypt` hash in the traditional 13-character form.
+    ///
+    /// Any other algorithm is formatted with `to_string`.
+    pub fn to_crypt_string(&self) -> Result<String> {
+        if self.algorithm != Hashes::DesCrypt {
+            return to_string(self);
+        }
+        DesCrypt::try_from(self.clone()).map(|hash| hash.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bcrypt_try_from() {
+        let bcrypt_hash = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+        let generic: McfHash = from_str(bcrypt_hash).unwrap();
+        let bcrypt = BcryptHash::try_from(generic).unwrap();
+        assert_eq!(to_string(&bcrypt).unwrap(), bcrypt_hash);
+
+        // The cost is a string once written out in the generic form.
+        let generic: McfHash = from_str(bcrypt_hash).unwrap();
+        let reparsed: McfHash = from_str(&to_string(&generic).unwrap()).unwrap();
+        let bcrypt = BcryptHash::try_from(reparsed).unwrap();
+        assert_eq!(to_string(&bcrypt).unwrap(), bcrypt_hash);
+
+        let argon: McfHash = from_str("$argon2i$m=512,t=3,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(BcryptHash::try_from(argon).is_err());
+        let mut no_cost: McfHash = from_str(bcrypt_hash).unwrap();
+        no_cost.parameters.clear();
+        assert!(BcryptHash::try_from(no_cost).is_err());
+    }
+
+    #[test]
+    fn test_bcrypt_mcf() {
+        // The salt and hash of the `$2a$` test vector, as produced by libpasta.
+        let mcf = "$2y-mcf$cost=10$emlGg0VF8guvY0qqhp6GTQ$O/Km4RsXehetgrZAwIr8AcsTfEOPBQg";
+        let hash: BcryptMcfHash = from_str(mcf).unwrap();
+        assert_eq!(hash.cost, 10);
+        assert_eq!(to_string(&hash).unwrap(), mcf);
+
+        let bcrypt: BcryptHash =
+            from_str("$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe").unwrap();
+        let (legacy, generic) = (McfHash::from(bcrypt), McfHash::from(hash.clone()));
+        assert_eq!((&legacy.salt, &legacy.hash), (&generic.salt, &generic.hash));
+        assert_eq!(from_str::<McfHash>(mcf).unwrap().salt, generic.salt);
+
+        let bare = "$2y-mcf$10$emlGg0VF8guvY0qqhp6GTQ$O/Km4RsXehetgrZAwIr8AcsTfEOPBQg";
+        assert_eq!(from_str::<BcryptMcfHash>(bare).unwrap(), hash);
+        assert!(from_str::<BcryptMcfHash>("$2y$cost=10$emlGg0VF8guvY0qqhp6GTQ\
+                                           $O/Km4RsXehetgrZAwIr8AcsTfEOPBQg")
+            .is_err());
+        assert!(from_str::<BcryptMcfHash>("$2y-mcf$cost=10$c2FsdA$aGFzaA").is_err());
+    }
+
+    #[test]
+    fn test_bcrypt_sha256() {
+        // Vectors from Passlib's documentation.
+        let v2 = "$bcrypt-sha256$v=2,t=2b,r=12$n79VH.0Q2TMWmt3Oqt9uku\
+                  $Kq4Noyk3094Y2QlB8NdRT8SvGiI4ft2";
+        let hash: BcryptSha256Hash = from_str(v2).unwrap();
+        assert_eq!((hash.version, &hash.variant, hash.cost), (2, &Hashes::Bcryptb, 12));
+        assert_eq!((hash.salt.len(), hash.hash.len()), (16, 23));
+        assert_eq!(to_string(&hash).unwrap(), v2);
+
+        let v1 = "$bcrypt-sha256$2a,12$LrmaIX5x4TRtAwEfwJZa1.$2ehnw6LvuIUTM0iz4iz9hTxv21B6KFO";
+        let hash: BcryptSha256Hash = from_str(v1).unwrap();
+        assert_eq!((hash.version, &hash.variant, hash.cost), (1, &Hashes::Bcrypta, 12));
+        assert_eq!(to_string(&hash).unwrap(), v1);
+
+        let salthash = "n79VH.0Q2TMWmt3Oqt9uku$Kq4Noyk3094Y2QlB8NdRT8SvGiI4ft2";
+        for &params in &["v=2,t=2b", "v=2,t=5,r=12", "v=3,t=2b,r=12", "2b"] {
+            let bad = format!("$bcrypt-sha256${}${}", params, salthash);
+            assert!(from_str::<BcryptSha256Hash>(&bad).is_err(), "{}", bad);
+        }
+        assert!(from_str::<BcryptSha256Hash>("$bcrypt-sha256$v=2,t=2b,r=12$c2FsdA$aGFzaA")
+            .is_err());
+        assert!(from_str::<BcryptSha256Hash>(&format!("$2b$v=2,t=2b,r=12${}", salthash)).is_err());
+    }
+
+    #[test]
+    fn test_scrypt_forms() {
+        // From the Passlib `scrypt` documentation.
+        let map_form = "$scrypt$ln=16,r=8,p=1$aM15713r3Xsvxbi31lqr1Q\
+                        $nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E";
+        // Passlib only writes the map form, and no producer of the packed
+        // form is known, so this is the same hash with the parameters packed
+        // as (16, 8, 1) by hand. It only checks the encoding against itself.
+        let packed_form = "$scrypt$EAAAAAgAAAAB$aM15713r3Xsvxbi31lqr1Q\
+                           $nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E";
+
+        let map_hash: ScryptHash = from_str(map_form).unwrap();
+        let packed_hash: ScryptHash = from_str(packed_form).unwrap();
+        assert!(!map_hash.params.packed);
+        assert!(packed_hash.params.packed);
+        for hash in &[&map_hash, &packed_hash] {
+            assert_eq!((hash.params.ln, hash.params.r, hash.params.p), (16, 8, 1));
+        }
+        assert_eq!(map_hash.hash, packed_hash.hash);
+
+        assert_eq!(to_string(&map_hash).unwrap(), map_form);
+        assert_eq!(to_string(&packed_hash).unwrap(), packed_form);
+
+        let generic: McfHash = packed_hash.into();
+        assert_eq!(to_string(&generic).unwrap(), map_form);
+        let generic: McfHash = from_str(map_form).unwrap();
+        assert_eq!(to_string(&generic).unwrap(), map_form);
+        assert_eq!(generic.salt.as_ref(), Some(&map_hash.salt));
+        assert_eq!(generic.hash, map_hash.hash);
+    }
+
+    #[test]
+    fn test_scrypt_param_order() {
+        let reordered = "$scrypt$r=8,p=1,ln=16$aM15713r3Xsvxbi31lqr1Q\
+                         $nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E";
+        let hash: ScryptHash = from_str(reordered).unwrap();
+        assert_eq!((hash.params.ln, hash.params.r, hash.params.p), (16, 8, 1));
+        assert_eq!(to_string(&hash).unwrap(), reordered);
+
+        let generic: McfHash = hash.clone().into();
+        assert_eq!(to_string(&generic).unwrap(), reordered);
+        assert_eq!(generic.scrypt_params(), Some(hash.params));
+
+        let n: McfHash = from_str("$scrypt$r=8,N=16384,p=1$c2FsdA$aGFzaA").unwrap();
+        let params = n.scrypt_params().unwrap();
+        let ordered = ScryptHash {
+            algorithm: Hashes::Scrypt,
+            params,
+            salt: b"salt".to_vec(),
+            hash: b"hash".to_vec(),
+        };
+        assert_eq!(to_string(&ordered).unwrap(), "$scrypt$r=8,ln=14,p=1$c2FsdA$aGFzaA");
+    }
+
+    #[test]
+    fn test_scrypt_params() {
+        let ln: McfHash = from_str("$scrypt$ln=14,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        let n: McfHash = from_str("$scrypt$N=16384,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        let params = ln.scrypt_params().unwrap();
+        assert_eq!((params.n(), params.r, params.p), (Some(16384), 8, 1));
+        assert_eq!(n.scrypt_params(), Some(params));
+
+        let odd: McfHash = from_str("$scrypt$N=1000,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(odd.scrypt_params(), None);
+        let argon: McfHash = from_str("$argon2i$ln=14,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(argon.scrypt_params(), None);
+    }
+
+    #[test]
+    fn test_scrypt_invalid_packed() {
+        assert!(from_str::<ScryptHash>("$scrypt$EAAAAAgA$c2FsdA$aGFzaA").is_err());
+        assert!(from_str::<ScryptHash>("$scrypt$ln=16,r=8$c2FsdA$aGFzaA").is_err());
+        assert!(from_str::<ScryptHash>("$scrypt$ln=16,r=8,ln=16$c2FsdA$aGFzaA").is_err());
+
+        // Parameters out of range parse, but have no `N`.
+        let huge: ScryptHash = from_str("$scrypt$ln=200,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(huge.params.n(), None);
+        let packed: ScryptHash = from_str("$scrypt$QAAAAAgAAAAB$c2FsdA$aGFzaA").unwrap();
+        assert_eq!((packed.params.ln, packed.params.n()), (64, None));
+    }
+
+    #[test]
+    fn test_md5_crypt() {
+        // From the Apache `htpasswd` documentation, for the password
+        // `myPassword`.
+        let apr1 = "$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/";
+        let hash: Md5CryptHash = from_str(apr1).unwrap();
+        assert_eq!(hash.algorithm, Hashes::AprMd5Crypt);
+        assert_eq!(hash.salt, "r31.....");
+        assert_eq!(hash.hash,
+                   vec![0x95, 0x5a, 0x60, 0x37, 0xdf, 0xf1, 0x5d, 0x8c, 0xc4, 0xe9, 0x0f, 0x4c,
+                        0x93, 0xae, 0x5c, 0xa4]);
+        assert_eq!(to_string(&hash).unwrap(), apr1);
+
+        let md5 = "$1$saltstri$qQY4WxjABChYG1ccLpfkz/";
+        let hash: Md5CryptHash = from_str(md5).unwrap();
+        assert_eq!(hash.algorithm, Hashes::Md5Crypt);
+        assert_eq!(to_string(&hash).unwrap(), md5);
+
+        assert!(from_str::<Md5CryptHash>("$1$saltstri$qQY4WxjABChYG1ccLpfkz").is_err());
+    }
+
+    #[test]
+    fn test_sha_crypt() {
+        // Test vectors from the SHA-crypt specification.
+        let sha512 = "$6$rounds=5000$toolongsaltstrin$lQ8jolhgVRVhY4b5pZKaysCLi0QBxGoNeKQzQ3\
+                      glMhwllF7oGDZxUhx1yxdYcz/e1JSbq3y6JMxxl8audkUEm0";
+        let hash: ShaCryptHash = from_str(sha512).unwrap();
+        assert_eq!(hash.algorithm, Hashes::Sha512Crypt);
+        assert_eq!(hash.salt, "toolongsaltstrin");
+        assert_eq!(hash.hash[..8], [0xbc, 0x74, 0x17, 0x1e, 0x75, 0xee, 0x35, 0xbd]);
+        assert_eq!(hash.hash[56..], [0x92, 0xe8, 0xe5, 0x23, 0x15, 0x62, 0x42, 0xb2]);
+        assert_eq!(to_string(&hash).unwrap(), sha512);
+
+        let sha256 = "$5$rounds=5000$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5";
+        let hash: ShaCryptHash = from_str(sha256).unwrap();
+        assert_eq!(hash.hash[..4], [0xec, 0xe9, 0x80, 0x7f]);
+        assert_eq!(to_string(&hash).unwrap(), sha256);
+
+        assert!(from_str::<ShaCryptHash>("$5$rounds=5000$saltstring$5B8vYYiY").is_err());
+    }
+
+    #[test]
+    fn test_sha_crypt_rounds() {
+        // Test vectors from the SHA-crypt specification.
+        let default = "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5";
+        let hash: ShaCryptHash = from_str(default).unwrap();
+        assert!(hash.parameters.is_empty());
+        assert_eq!(hash.salt, "saltstring");
+        assert_eq!(hash.rounds(), 5000);
+        assert_eq!(to_string(&hash).unwrap(), default);
+
+        let explicit = "$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA";
+        let hash: ShaCryptHash = from_str(explicit).unwrap();
+        assert_eq!(hash.parameters["rounds"], Value::Number(10000.into()));
+        assert_eq!(hash.salt, "saltstringsaltst");
+        assert_eq!(hash.rounds(), 10000);
+        assert_eq!(to_string(&hash).unwrap(), explicit);
+
+        assert!(from_str::<ShaCryptHash>("$5$rounds=x$saltstring\
+                                          $5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5")
+            .is_err());
+    }
+
+    #[test]
+    fn test_pbkdf2_digest_len() {
+        // Generated for password "password" with Python's `hashlib`.
+        let vectors = [
+            ("$pbkdf2$29000$9t7be09prfXee2/NOUeotQ$7/z3i1GMBkbgUMptWk0TVH41p7c",
+             Hashes::Pbkdf2Sha1, 20),
+            ("$pbkdf2-sha256$29000$9t7be09prfXee2/NOUeotQ\
+              $yIPphVdF97u.XzygN1ToroW.pJoenSO2MPWQIaDuH7Y",
+             Hashes::Pbkdf2Sha256, 32),
+            ("$pbkdf2-sha512$29000$9t7be09prfXee2/NOUeotQ\
+              $Sp00YehldJHzq2us.DrswK0X8435gKHmHNok8u/6/Eg0wT0MheRKzcsbJIRFDl1WZNWvWRns34KB7G6TuyfkhA",
+             Hashes::Pbkdf2Sha512, 64),
+        ];
+        for &(vector, ref alg, len) in &vectors {
+            let hash: Pbkdf2Hash = from_str(vector).unwrap();
+            assert_eq!(hash.algorithm, *alg);
+            assert_eq!(hash.rounds, 29000);
+            assert_eq!(hash.salt.len(), 16);
+            assert_eq!(hash.hash.len(), len);
+            assert_eq!(to_string(&hash).unwrap(), vector);
+        }
+
+        // Truncated digests.
+        assert!(from_str::<Pbkdf2Hash>("$pbkdf2$29000$9t7be09prfXee2/NOUeotQ\
+                                        $7/z3i1GMBkbgUMptWk0TVH41").is_err());
+        assert!(from_str::<Pbkdf2Hash>("$pbkdf2-sha512$29000$9t7be09prfXee2/NOUeotQ\
+                                        $yIPphVdF97u.XzygN1ToroW.pJoenSO2MPWQIaDuH7Y")
+            .is_err());
+        // A sha512 digest under the sha256 identifier.
+        assert!(from_str::<Pbkdf2Hash>("$pbkdf2-sha256$29000$9t7be09prfXee2/NOUeotQ\
+                                        $Sp00YehldJHzq2us.DrswK0X8435gKHmHNok8u/6/Eg0wT0M\
+                                        heRKzcsbJIRFDl1WZNWvWRns34KB7G6TuyfkhA")
+            .is_err());
+        assert!(from_str::<Pbkdf2Hash>("$5$29000$9t7be09prfXee2/NOUeotQ\
+                                        $yIPphVdF97u.XzygN1ToroW.pJoenSO2MPWQIaDuH7Y")
+            .is_err());
+    }
+
+    #[test]
+    fn test_combined_salt_hash() {
+        // A bcrypt-shaped hash with a 16-byte salt and 23-byte digest, encoded
+        // as a single standard base64 field.
+        let bcrypt = "$2b$cost=10$AAECAwQFBgcICQoLDA0OD2RlZmdoaWprbG1ub3BxcnN0dXZ3eHl6";
+        let hash = from_str_combined(bcrypt, CombinedSaltHash::SaltFirst(16)).unwrap();
+        assert_eq!(hash.algorithm, Hashes::Bcryptb);
+        assert_eq!(hash.salt, Some((0..16).collect()));
+        assert_eq!(hash.hash, (100..123).collect::<Vec<u8>>());
+
+        // SHA1("password" + salt) followed by the 8-byte salt, as in `{SSHA}`.
+        let ssha = "$custom$$N+vXsN2ny8mTqd6ZYuHcJVHvE00BAgMEBQYHCA";
+        let hash = from_str_combined(ssha, CombinedSaltHash::HashFirst(20)).unwrap();
+        assert_eq!(hash.salt, Some(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(hash.hash.len(), 20);
+        assert_eq!(hash.hash[..4], [0x37, 0xeb, 0xd7, 0xb0]);
+
+        assert!(from_str_combined(ssha, CombinedSaltHash::HashFirst(32)).is_err());
+        assert!(from_str_combined("$custom$$c2FsdA$aGFzaA", CombinedSaltHash::SaltFirst(2))
+            .is_err());
+    }
+
+    #[test]
+    fn test_des_crypt() {
+        let des = "abJnggxhB/yWI";
+        let hash = from_crypt_str(des).unwrap();
+        assert_eq!(hash.algorithm, Hashes::DesCrypt);
+        // 'a' = 38, 'b' = 39, so the salt is 38 + (39 << 6).
+        assert_eq!(hash.salt, Some(vec![0xe6, 0x09]));
+        assert_eq!(hash.hash.len(), 8);
+        assert_eq!(hash.to_crypt_string().unwrap(), des);
+
+        assert_eq!(from_crypt_str("./H7.I.sVn7zo").unwrap().to_crypt_string().unwrap(),
+                   "./H7.I.sVn7zo");
+        assert!(from_crypt_str("abJnggxhB/yW").is_err());
+        assert!(from_crypt_str("a!JnggxhB/yWI").is_err());
+        assert!(from_crypt_str("abJnggxhB/yW!").is_err());
+        // crypt("test", "Xy"), checking the salt's character order.
+        let hash = from_crypt_str("Xy84zCXgG74kA").unwrap();
+        assert_eq!(hash.salt, Some(vec![0xa3, 0x0f]));
+        assert_eq!(hash.to_crypt_string().unwrap(), "Xy84zCXgG74kA");
+        assert_eq!(from_crypt_str("$argon2i$m=1$c2FsdA$aGFzaA").unwrap().algorithm,
+                   Hashes::Argon2i);
+
+        let typed: DesCrypt = "Xy84zCXgG74kA".parse().unwrap();
+        assert_eq!(typed.salt, 0xfa3);
+        assert_eq!(typed.to_string(), "Xy84zCXgG74kA");
+        let generic = McfHash::from(typed.clone());
+        assert_eq!(generic, hash);
+        assert_eq!(DesCrypt::try_from(generic).unwrap(), typed);
+        assert!("abJnggxhB/yW".parse::<DesCrypt>().is_err());
+        assert!(DesCrypt::try_from(from_str::<McfHash>("$argon2i$m=1$c2FsdA$aGFzaA").unwrap())
+            .is_err());
+        let mut wide = hash.clone();
+        wide.salt = Some(vec![0, 0x10]);
+        assert!(DesCrypt::try_from(wide).is_err());
+    }
+}
@@ -1,102 +1,905 @@
-/// Serde functionality for the `ModularCryptFormat`.
-///
-/// This is informally defined in the following way:
-///
-/// Fields are delimited by $ signs, and are simply decoded in order.
-/// So the struct Foo { x: 12, y: 37} serializers to/from the string `$12$37`.
-///
-/// Fields can either be `UnitVariants`, and decode by name, single values,
-/// or Maps in the form key=value,...,. Finally, a field can also contain a
-/// byte array, which by default serializes to a base64 string, unpadded.
+//! Serde functionality for the `ModularCryptFormat`.
+//!
+//! This is informally defined in the following way:
+//!
+//! Fields are delimited by $ signs, and are simply decoded in order.
+//! So the struct Foo { x: 12, y: 37} serializers to/from the string `$12$37`.
+//!
+//! Fields can either be `UnitVariants`, and decode by name, single values,
+//! or Maps in the form key=value,...,. Finally, a field can also contain a
+//! byte array, which by default serializes to a base64 string, unpadded.
+//!
+//! Without the default `std` feature, the crate only needs `alloc`. Writing
+//! to an `io::Write` with `to_writer` and `McfWriter` is then unavailable.
 
-extern crate data_encoding;
-#[macro_use]
-extern crate error_chain;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
 #[macro_use]
-extern crate lazy_static;
+extern crate alloc;
+extern crate data_encoding;
+extern crate once_cell;
 #[macro_use]
 extern crate serde;
-extern crate serde_bytes;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+#[cfg(feature = "zeroize")]
+#[macro_use]
+extern crate zeroize;
+
+#[cfg(test)]
+extern crate arrayvec;
+#[cfg(test)]
+#[macro_use]
+extern crate lazy_static;
+#[cfg(test)]
+extern crate serde_bytes;
+
+// Without `std`, the `std::` paths used throughout the crate resolve to
+// `core` and `alloc` through this module instead.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::*;
+    pub use alloc::{boxed, collections, fmt, string, vec};
+}
+
+// The parts of the standard prelude which come from `alloc`, imported by each
+// module so that they are in scope without `std`.
+mod prelude {
+    pub use std::boxed::Box;
+    pub use std::string::{String, ToString};
+    pub use std::vec::Vec;
+}
+
+pub mod argon2_params;
+pub use argon2_params::Argon2Params;
 
 pub mod de;
-pub use de::{from_str, McfDeserializer};
+pub use de::{fields, fields_with_dialect, from_str, from_str_flat, from_str_into,
+             from_str_limited, from_str_strict, parse_parts, parse_parts_with_dialect,
+             peek_algorithm, peek_algorithm_with_dialect, McfDeserializer, McfParts};
+
+mod dialect;
+pub use dialect::Dialect;
 
-mod encoding;
+pub mod encoding;
 pub use encoding::base64;
 pub use encoding::base64bcrypt;
+pub use encoding::md5crypt;
+pub use encoding::sha2crypt;
+pub use encoding::yescrypt;
+use encoding::ab64;
+
+mod header;
+
+mod phc;
+mod schema;
+pub use schema::{CostSummary, Family, SecurityStatus};
 
 pub mod ser;
-pub use ser::{to_string, McfSerializer};
+pub use ser::{to_annotated_string, to_string, to_string_in, to_string_with_options,
+              McfSerializer};
+#[cfg(feature = "std")]
+pub use ser::{to_writer, to_writer_with_options, McfWriter};
+
+mod shadow;
+pub use shadow::{parse_shadow, parse_shadow_line};
+
+mod verify;
+pub use verify::{verify, VerifierRegistry};
 
 pub use serde_json::{Map, Value};
 
+use prelude::*;
+use std::convert::TryFrom;
+
 pub mod errors {
+    //! The error type returned when serializing or deserializing.
+
     use data_encoding;
+    use prelude::*;
+    use std::error;
+    use std::fmt;
+    #[cfg(feature = "std")]
     use std::io;
+    use std::result;
 
-    error_chain!{
-        errors {
-            Custom(msg: String)
-            Unsupported
+    /// The kinds of error which can occur.
+    ///
+    /// The `Io` variant is only present with the `std` feature.
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// A plain error message.
+        Msg(String),
+        /// An error raised by a `Serialize` or `Deserialize` implementation.
+        Custom(String),
+        /// A value which the MCF serializer cannot write.
+        Unsupported(&'static str),
+        /// An algorithm which the operation does not handle.
+        UnsupportedAlgorithm(String),
+        /// The names of the missing and invalid algorithm parameters.
+        InvalidParameters(Vec<String>, Vec<String>),
+        /// A byte which is not allowed, and its position in the input.
+        InvalidCharacter(usize, u8),
+        /// The name and byte offset of a field which failed to parse, and
+        /// the reason.
+        InvalidField(String, usize, String),
+        /// An algorithm identifier which is not one of the `Hashes`.
+        UnknownAlgorithm(String),
+        /// The input does not start with `$`.
+        MissingLeadingDelimiter,
+        /// The length of the input and the limit it exceeds.
+        InputTooLong(usize, usize),
+        /// The expected and actual number of fields.
+        FieldCount(usize, usize),
+        /// Invalid encoded bytes.
+        Decoding(data_encoding::DecodeError),
+        /// An error from a `fmt::Write`, or a `ser::Write` other than an
+        /// `io::Write`.
+        Fmt(fmt::Error),
+        /// An error from the underlying writer.
+        #[cfg(feature = "std")]
+        Io(io::Error),
+    }
+
+    impl fmt::Display for ErrorKind {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                ErrorKind::Msg(ref msg) | ErrorKind::Custom(ref msg) => f.write_str(msg),
+                ErrorKind::Unsupported(construct) => {
+                    write!(f, "serializing a `{}` is not supported", construct)
+                }
+                ErrorKind::UnsupportedAlgorithm(ref id) => {
+                    write!(f, "algorithm `{}` is not supported here", id)
+                }
+                ErrorKind::InvalidParameters(ref missing, ref invalid) => {
+                    write!(f, "missing parameters: {:?}, invalid parameters: {:?}", missing, invalid)
+                }
+                ErrorKind::InvalidCharacter(position, byte) => {
+                    write!(f, "invalid byte 0x{:02x} at position {}", byte, position)
+                }
+                ErrorKind::InvalidField(ref field, offset, ref reason) => {
+                    write!(f, "invalid {} field at byte {}: {}", field, offset, reason)
+                }
+                ErrorKind::UnknownAlgorithm(ref id) => {
+                    write!(f, "unknown algorithm identifier `{}`", id)
+                }
+                ErrorKind::MissingLeadingDelimiter => {
+                    f.write_str("MCF input does not start with `$`")
+                }
+                ErrorKind::InputTooLong(len, limit) => {
+                    write!(f, "input of {} bytes is longer than the limit of {}", len, limit)
+                }
+                ErrorKind::FieldCount(expected, found) => {
+                    write!(f, "expected {} fields, found {}", expected, found)
+                }
+                ErrorKind::Decoding(ref e) => fmt::Display::fmt(e, f),
+                ErrorKind::Fmt(ref e) => fmt::Display::fmt(e, f),
+                #[cfg(feature = "std")]
+                ErrorKind::Io(ref e) => fmt::Display::fmt(e, f),
+            }
         }
+    }
+
+    /// The error type of this crate.
+    #[derive(Debug)]
+    pub struct Error(ErrorKind);
 
-        foreign_links {
-            Decoding(data_encoding::DecodeError);
-            Io(io::Error);
+    impl Error {
+        /// The kind of error.
+        pub fn kind(&self) -> &ErrorKind {
+            &self.0
         }
     }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Display::fmt(&self.0, f)
+        }
+    }
+
+    impl error::Error for Error {
+        fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+            match self.0 {
+                #[cfg(feature = "std")]
+                ErrorKind::Decoding(ref e) => Some(e),
+                ErrorKind::Fmt(ref e) => Some(e),
+                #[cfg(feature = "std")]
+                ErrorKind::Io(ref e) => Some(e),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<ErrorKind> for Error {
+        fn from(kind: ErrorKind) -> Error {
+            Error(kind)
+        }
+    }
+
+    impl<'a> From<&'a str> for Error {
+        fn from(msg: &'a str) -> Error {
+            Error(ErrorKind::Msg(msg.to_string()))
+        }
+    }
+
+    impl From<String> for Error {
+        fn from(msg: String) -> Error {
+            Error(ErrorKind::Msg(msg))
+        }
+    }
+
+    impl From<data_encoding::DecodeError> for Error {
+        fn from(e: data_encoding::DecodeError) -> Error {
+            Error(ErrorKind::Decoding(e))
+        }
+    }
+
+    impl From<fmt::Error> for Error {
+        fn from(e: fmt::Error) -> Error {
+            Error(ErrorKind::Fmt(e))
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl From<io::Error> for Error {
+        fn from(e: io::Error) -> Error {
+            Error(ErrorKind::Io(e))
+        }
+    }
+
+    /// A `Result` with this crate's `Error`.
+    pub type Result<T> = result::Result<T, Error>;
 }
 
 /// A generic hash converted from the `ModularCryptFormat`.
-#[derive(Debug, Deserialize, Serialize)]
+///
+/// Hashes without a salt have the form `$alg$params$hash`, and are parsed
+/// with `salt: None`. An empty salt, as in `$alg$params$$hash`, is parsed as
+/// `Some` empty salt, and written back the same way. Bcrypt hashes such as
+/// `$2b$10$<salthash>` are also accepted, with the cost stored as the `cost`
+/// parameter. Parameters embedded in the algorithm field, as in
+/// `$argon2id,v=19$m=65536,t=2,p=1$...`, are read ahead of the others.
+/// Argon2 hashes may also have the version in a field of its own, as in
+/// `$argon2id$v=19$m=65536,t=2,p=1$...`, which is how their `v` parameter is
+/// written.
+///
+/// The salt and hash are base64 encoded, except for the sha-crypt (`$5$`,
+/// `$6$`), md5-crypt (`$1$`, `$apr1$`, `$md5$`) and yescrypt (`$y$`)
+/// algorithms. These use the salt verbatim and their own encoding for the
+/// digest, as in `$5$salt$digest`, and the `rounds` parameter is optional.
+/// Passlib's pbkdf2 hashes (`$pbkdf2-sha256$` and so on) use its adapted
+/// base64, as in `encoding::ab64`.
+///
+/// With the `zeroize` feature enabled, the salt and hash are wiped when the
+/// `McfHash` is dropped. Strings produced from it, such as by `to_string`,
+/// are not.
+///
+/// The fields are public, but `digest` and `salt_bytes` are the preferred
+/// way to read the decoded hash and salt, as they will keep working if their
+/// representation changes.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "zeroize", derive(Zeroize, ZeroizeOnDrop))]
 pub struct McfHash {
+    #[cfg_attr(feature = "zeroize", zeroize(skip))]
     pub algorithm: Hashes,
+    #[cfg_attr(feature = "zeroize", zeroize(skip))]
     pub parameters: Map<String, Value>,
-    #[serde(with = "base64")]
-    pub salt: Vec<u8>,
-    #[serde(with = "base64")]
+    pub salt: Option<Vec<u8>>,
     pub hash: Vec<u8>,
 }
 
-pub mod legacy {
-    use super::*;
-    /// MCF style `Bcrypt` hash
-    #[derive(Debug, Deserialize, Serialize)]
-    pub struct BcryptHash {
-        algorithm: Hashes,
-        cost: u8,
-        #[serde(with = "base64bcrypt")]
-        salthash: (Vec<u8>, Vec<u8>),
+impl serde::Serialize for McfHash {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        use serde::ser::{Error, SerializeStruct};
+
+        // Encodes a salt or hash with the given encoding.
+        struct Encoded<'a>(FieldEncoding, &'a [u8]);
+
+        impl<'a> serde::Serialize for Encoded<'a> {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+                where S: serde::Serializer
+            {
+                match self.0 {
+                    FieldEncoding::Base64 => base64::serialize(&self.1, serializer),
+                    FieldEncoding::Ab64 => ab64::serialize(&self.1, serializer),
+                    FieldEncoding::Verbatim => {
+                        let field = std::str::from_utf8(self.1)
+                            .map_err(|_| S::Error::custom("crypt salt must be valid UTF-8"))?;
+                        serializer.serialize_str(field)
+                    }
+                    FieldEncoding::Sha2Crypt => sha2crypt::serialize(&self.1, serializer),
+                    FieldEncoding::Md5Crypt => md5crypt::serialize(&self.1, serializer),
+                    FieldEncoding::Yescrypt => yescrypt::serialize(&self.1, serializer),
+                }
+            }
+        }
+
+        let (salt_encoding, hash_encoding) = self.algorithm.field_encodings();
+        let crypt = self.algorithm.is_crypt();
+        let mut state = serializer.serialize_struct("McfHash", 4)?;
+        if self.algorithm == Hashes::SunMd5Crypt {
+            // Sun's md5-crypt has any parameters in the same field as the
+            // algorithm, as in `$md5,rounds=5000$salt$hash`.
+            let mut field = self.algorithm.to_id().to_string();
+            for (key, value) in &self.parameters {
+                field.push_str(&format!(",{}={}", key, phc::value_to_string(value)));
+            }
+            state.serialize_field("algorithm", &field)?;
+            state.skip_field("parameters")?;
+        } else {
+            state.serialize_field("algorithm", &self.algorithm)?;
+            let versioned = self.algorithm.is_argon2() && self.parameters.contains_key("v") &&
+                            self.parameters.len() > 1;
+            let bare = match self.parameters.iter().next() {
+                Some((key, Value::String(value))) if self.parameters.len() == 1 &&
+                                                     value.is_empty() => Some(key),
+                _ => None,
+            };
+            if crypt && self.parameters.is_empty() {
+                state.skip_field("parameters")?;
+            } else if let Some(key) = bare {
+                // A lone value without a key, such as Passlib's pbkdf2 rounds
+                // in `$pbkdf2-sha256$29000$...`, is written back as it was.
+                state.serialize_field("parameters", key)?;
+            } else if versioned {
+                // Argon2's version is written in a field of its own, as in
+                // `$argon2id$v=19$m=65536,t=2,p=1$...`.
+                state.serialize_field("parameters", &VersionedParams(&self.parameters))?;
+            } else {
+                state.serialize_field("parameters", &self.parameters)?;
+            }
+        }
+        match self.salt {
+            Some(ref salt) => state.serialize_field("salt", &Encoded(salt_encoding, salt))?,
+            None => state.skip_field("salt")?,
+        }
+        state.serialize_field("hash", &Encoded(hash_encoding, &self.hash))?;
+        state.end()
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for McfHash {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<McfHash, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        use serde::Deserialize;
+        use serde::de::{Error, IntoDeserializer};
+
+        // With three fields, the last one is the hash rather than the salt,
+        // unless it is bcrypt's packed salt and hash. The encoded fields are
+        // decoded once we know which, except that fields which are base64
+        // either way are decoded as they are read, so that errors point at
+        // the field.
+        // The salt is `Some(None)` if the field is there but empty.
+        struct RawMcfHash {
+            algorithm: Hashes,
+            // Parameters embedded in the algorithm field.
+            embedded: Map<String, Value>,
+            parameters: RawParams,
+            salt: Option<Option<RawField>>,
+            hash: Option<RawField>,
+        }
+
+        enum RawField {
+            Encoded(String),
+            Decoded(Vec<u8>),
+        }
+
+        impl RawField {
+            fn into_encoded<E: Error>(self) -> std::result::Result<String, E> {
+                match self {
+                    RawField::Encoded(field) => Ok(field),
+                    RawField::Decoded(_) => Err(E::custom("field was already decoded")),
+                }
+            }
+        }
+
+        // Reads a salt or hash, decoding it if `algorithm` is known to use
+        // base64.
+        struct RawFieldSeed<'a>(Option<&'a Hashes>);
+
+        impl<'a, 'de> serde::de::DeserializeSeed<'de> for RawFieldSeed<'a> {
+            type Value = Option<RawField>;
+
+            fn deserialize<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+                where D: serde::Deserializer<'de>
+            {
+                let field = match Option::<String>::deserialize(deserializer)? {
+                    Some(field) => field,
+                    None => return Ok(None),
+                };
+                match self.0.filter(|alg| !alg.is_bcrypt()).and_then(|alg| {
+                    alg.field_encodings().0.base64()
+                }) {
+                    Some(encoding) => {
+                        encoding.decode(field.as_bytes())
+                            .map(|bytes| Some(RawField::Decoded(bytes)))
+                            .map_err(|e| D::Error::custom(format!("invalid base64: {}", e)))
+                    }
+                    None => Ok(Some(RawField::Encoded(field))),
+                }
+            }
+        }
+
+        struct RawVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for RawVisitor {
+            type Value = RawMcfHash;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an MCF hash")
+            }
+
+            fn visit_map<A>(self, mut access: A) -> std::result::Result<RawMcfHash, A::Error>
+                where A: serde::de::MapAccess<'de>
+            {
+                let (mut algorithm, mut parameters, mut salt, mut hash) = (None, None, None, None);
+                let mut embedded = Map::new();
+                while let Some(key) = access.next_key::<String>()? {
+                    match key.as_str() {
+                        "algorithm" => {
+                            let AlgorithmId(field) = access.next_value()?;
+                            let (alg, entries) = split_algorithm(&field);
+                            algorithm = Some(alg);
+                            embedded = entries;
+                        }
+                        "parameters" => {
+                            let versioned = algorithm.as_ref().is_some_and(Hashes::is_argon2);
+                            parameters = Some(access.next_value_seed(RawParamsSeed(versioned))?)
+                        }
+                        "salt" => {
+                            salt = Some(access.next_value_seed(RawFieldSeed(algorithm.as_ref()))?)
+                        }
+                        "hash" => hash = access.next_value_seed(RawFieldSeed(algorithm.as_ref()))?,
+                        _ => {
+                            access.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(RawMcfHash {
+                    algorithm: algorithm.ok_or_else(|| A::Error::missing_field("algorithm"))?,
+                    embedded,
+                    parameters: parameters.ok_or_else(|| A::Error::missing_field("parameters"))?,
+                    salt,
+                    hash,
+                })
+            }
+        }
+
+        const FIELDS: &[&str] = &["algorithm", "parameters", "salt", "hash"];
+        let raw = deserializer.deserialize_struct("McfHash", FIELDS, RawVisitor)?;
+        let mut parameters = raw.embedded;
+        parameters.extend(raw.parameters.0);
+        let bare = raw.parameters.1;
+        let encoding = raw.algorithm.field_encodings().0.base64().unwrap_or(BASE64);
+        let decode = |field: RawField| match field {
+            RawField::Decoded(bytes) => Ok(bytes),
+            RawField::Encoded(field) => encoding.decode(field.as_bytes()).map_err(D::Error::custom),
+        };
+        if raw.algorithm.is_crypt() {
+            // Sun's md5-crypt has the salt in the same field as any
+            // parameters, as in `$md5,rounds=5000$salt$hash`, so it is read
+            // as the last parameter. The salt may be followed by `$$`, in
+            // which case the `$` is part of the hashed salt, and is kept.
+            let sun_salt = match bare {
+                Some(ref salt) if raw.algorithm == Hashes::SunMd5Crypt &&
+                                  parameters.keys().next_back() == Some(salt) => {
+                    parameters.remove(salt);
+                    Some(salt.clone())
+                }
+                _ => None,
+            };
+            let (salt, hash) = match (sun_salt, raw.salt.and_then(|salt| salt), raw.hash) {
+                // The empty field between `$$` is read as a missing salt.
+                (Some(salt), None, Some(hash)) => (salt + "$", hash.into_encoded()?),
+                (Some(_), Some(_), Some(_)) => {
+                    return Err(D::Error::custom("too many fields in Sun md5-crypt hash"));
+                }
+                (Some(salt), Some(hash), None) => (salt, hash.into_encoded()?),
+                (None, Some(salt), Some(hash)) => (salt.into_encoded()?, hash.into_encoded()?),
+                // Without `rounds`, as in `$5$salt$hash`, the salt was read as
+                // the parameters.
+                (None, Some(hash), None) => {
+                    let salt = match bare {
+                        Some(salt) if parameters.len() == 1 => salt,
+                        None if parameters.is_empty() => String::new(),
+                        _ => return Err(D::Error::custom("invalid crypt salt")),
+                    };
+                    parameters.clear();
+                    (salt, hash.into_encoded()?)
+                }
+                _ => return Err(D::Error::missing_field("hash")),
+            };
+            let hash = match raw.algorithm.field_encodings().1 {
+                FieldEncoding::Sha2Crypt => sha2crypt::deserialize(hash.into_deserializer())?,
+                FieldEncoding::Md5Crypt => md5crypt::deserialize(hash.into_deserializer())?,
+                FieldEncoding::Yescrypt => yescrypt::deserialize(hash.into_deserializer())?,
+                _ => hash.into_bytes(),
+            };
+            return Ok(McfHash {
+                algorithm: raw.algorithm,
+                parameters,
+                salt: Some(salt.into_bytes()),
+                hash,
+            });
+        }
+        let (salt, hash) = match (raw.salt, raw.hash) {
+            (Some(Some(RawField::Encoded(salthash))), None) if raw.algorithm.is_bcrypt() &&
+                                                                salthash.len() == 53 => {
+                let (salt, hash) = base64bcrypt::deserialize(salthash.into_deserializer())?;
+                (Some(salt), hash)
+            }
+            // An empty salt field is read as `None`, but is still there.
+            (Some(salt), Some(hash)) => {
+                (Some(salt.map(decode).transpose()?.unwrap_or_default()), decode(hash)?)
+            }
+            (None, Some(hash)) | (Some(Some(hash)), None) => (None, decode(hash)?),
+            (_, None) => return Err(D::Error::missing_field("hash")),
+        };
+        // A lone value without a key is bcrypt's cost, as in `$2b$10$...`.
+        if let Some(cost) = bare.and_then(|key| key.parse::<u64>().ok()) {
+            if raw.algorithm.is_bcrypt() && parameters.len() == 1 {
+                parameters.clear();
+                parameters.insert("cost".to_string(), Value::Number(cost.into()));
+            }
+        }
+        Ok(McfHash {
+            algorithm: raw.algorithm,
+            parameters,
+            salt,
+            hash,
+        })
+    }
+}
+
+impl McfHash {
+    /// Parse `input` into this hash, reusing the existing salt and hash
+    /// buffers.
+    ///
+    /// This is equivalent to `*self = from_str(input)?`, but avoids
+    /// allocating new buffers when parsing many hashes in a loop. On error,
+    /// the contents of `self` are unspecified.
+    pub fn parse_into(&mut self, input: &str) -> errors::Result<()> {
+        #[derive(Deserialize)]
+        struct Head {
+            algorithm: AlgorithmId,
+            parameters: Map<String, Value>,
+        }
+
+        // Argon2 hashes with the version in a field of its own, as in
+        // `$argon2id$v=19$m=65536,t=2,p=1$...`, have five fields.
+        let parts = match parse_parts(input) {
+            Ok(parts) => parts,
+            Err(_) => {
+                *self = from_str(input)?;
+                return Ok(());
+            }
+        };
+        let encoding = match Hashes::from_id(parts.algorithm) {
+            Some(ref alg) if alg.is_bcrypt() && parts.salt.is_none() => None,
+            Some(ref alg) => alg.field_encodings().0.base64(),
+            None => Some(BASE64),
+        };
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => {
+            // Bcrypt's packed salt and hash, and the crypt digests, are
+                // decoded separately anyway.
+                *self = from_str(input)?;
+                return Ok(());
+            }
+        };
+        let head: Head = from_str(&input[..parts.algorithm.len() + parts.params.len() + 2])?;
+        let (algorithm, embedded) = split_algorithm(&head.algorithm.0);
+        self.algorithm = algorithm;
+        self.parameters = embedded;
+        self.parameters.extend(head.parameters);
+        match parts.salt {
+            Some(salt) => {
+                decode_into(encoding, input, "salt", salt, self.salt.get_or_insert_with(Vec::new))?
+            }
+            None => self.salt = None,
+        }
+        decode_into(encoding, input, "hash", parts.hash, &mut self.hash)
+    }
+
+    /// The decoded hash, such as for passing to a verifier.
+    pub fn digest(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// The decoded salt, if the hash has one.
+    pub fn salt_bytes(&self) -> Option<&[u8]> {
+        self.salt.as_deref()
+    }
+
+    /// Convert this hash to a JSON object with the keys `algorithm`,
+    /// `parameters`, `salt` and `hash`, for logging or storing in a document
+    /// database.
+    ///
+    /// The salt and hash are strings in the same encoding as in the MCF
+    /// string, and `salt` is left out if there is none. This is the same as
+    /// `serde_json::to_value`, and can be read back with
+    /// `serde_json::from_value`.
+    pub fn to_json(&self) -> errors::Result<Value> {
+        serde_json::to_value(self).map_err(|e| e.to_string().into())
+    }
+
+    /// Rebuild the parameters by applying `f` to each key and value in
+    /// turn, keeping their order. Entries for which `f` returns `None` are
+    /// dropped.
+    ///
+    /// This is for migrating hashes, such as replacing scrypt's `ln` with an
+    /// explicit `N`.
+    pub fn map_parameters<F>(mut self, mut f: F) -> McfHash
+        where F: FnMut(&str, Value) -> Option<(String, Value)>
+    {
+        let parameters = std::mem::take(&mut self.parameters);
+        self.parameters = parameters.into_iter()
+            .filter_map(|(key, value)| f(&key, value))
+            .collect();
+        self
+    }
+}
+
+/// An `McfHash` along with the original text of its salt and hash fields.
+///
+/// Re-encoding the decoded salt and hash does not always give back the
+/// input, for example for bcrypt's packed salt and hash, so this keeps the
+/// exact substrings for auditing and logging. Returned by
+/// `McfHash::from_str_with_raw`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawMcfHash<'a> {
+    /// The parsed hash.
+    pub hash: McfHash,
+    raw_salt: Option<&'a str>,
+    raw_hash: &'a str,
+}
+
+impl<'a> RawMcfHash<'a> {
+    /// The salt as it appeared in the input, if there was one.
+    pub fn raw_salt_str(&self) -> Option<&'a str> {
+        self.raw_salt
+    }
+
+    /// The hash as it appeared in the input.
+    pub fn raw_hash_str(&self) -> &'a str {
+        self.raw_hash
+    }
+}
+
+impl McfHash {
+    /// Parse `input` as with `from_str`, also keeping the raw text of the
+    /// salt and hash.
+    ///
+    /// For bcrypt's packed form, `$2b$10$<salthash>`, the raw salt is the
+    /// first 22 characters and the raw hash the rest. For the crypt
+    /// algorithms, such as `$5$salt$hash`, the raw salt is the text of the
+    /// verbatim salt, wherever it was read from.
+    pub fn from_str_with_raw(input: &str) -> errors::Result<RawMcfHash<'_>> {
+        let hash: McfHash = from_str(input)?;
+        if hash.algorithm.is_crypt() {
+            // The salt may be in the parameters field, or end with a `$` for
+            // Sun's md5-crypt, but is always just before the hash.
+            let raw_hash = input.rsplit('$').next().unwrap_or(input);
+            let salt_end = input.len() - raw_hash.len() - 1;
+            let raw_salt = hash.salt.as_ref().and_then(|salt| {
+                let start = salt_end.checked_sub(salt.len())?;
+                input.get(start..salt_end).filter(|raw| raw.as_bytes() == &salt[..])
+            });
+            return Ok(RawMcfHash {
+                hash,
+                raw_salt,
+                raw_hash,
+            });
+        }
+        let parts = parse_parts(input)?;
+        let (raw_salt, raw_hash) = match parts.salt {
+            None if hash.algorithm.is_bcrypt() && hash.salt.is_some() => {
+                let (salt, hash) = parts.hash.split_at(22);
+                (Some(salt), hash)
+            }
+            salt => (salt, parts.hash),
+        };
+        Ok(RawMcfHash {
+            hash,
+            raw_salt,
+            raw_hash,
+        })
+    }
+}
+
+// Decode the base64 field `name` into `out`, reusing its allocation. The
+// field must be a slice of `input`, to report where it starts on error.
+fn decode_into(encoding: &data_encoding::Encoding,
+               input: &str,
+               name: &str,
+               field: &str,
+               out: &mut Vec<u8>)
+               -> errors::Result<()> {
+    let invalid = |e: data_encoding::DecodeError| -> errors::Error {
+        let offset = field.as_ptr() as usize - input.as_ptr() as usize;
+        errors::ErrorKind::InvalidField(name.to_string(), offset, format!("invalid base64: {}", e))
+            .into()
+    };
+    let len = encoding.decode_len(field.len()).map_err(invalid)?;
+    out.clear();
+    out.resize(len, 0);
+    let written = encoding.decode_mut(field.as_bytes(), out)
+        .map_err(|e| invalid(e.error))?;
+    out.truncate(written);
+    Ok(())
+}
+
+// An algorithm identifier, as written by `Hashes`, before it is split by
+// `split_algorithm`.
+struct AlgorithmId(String);
+
+impl<'de> serde::Deserialize<'de> for AlgorithmId {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<AlgorithmId, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        use serde::de::VariantAccess;
+
+        struct IdVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for IdVisitor {
+            type Value = AlgorithmId;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an algorithm identifier")
+            }
+
+            fn visit_str<E>(self, id: &str) -> std::result::Result<AlgorithmId, E> {
+                Ok(AlgorithmId(id.to_string()))
+            }
+
+            fn visit_enum<A>(self, data: A) -> std::result::Result<AlgorithmId, A::Error>
+                where A: serde::de::EnumAccess<'de>
+            {
+                let (id, variant) = data.variant::<String>()?;
+                variant.unit_variant()?;
+                Ok(AlgorithmId(id))
+            }
+        }
+
+        // `Hashes` are written as unit variants.
+        deserializer.deserialize_enum("Hashes", &[], IdVisitor)
+    }
+}
+
+// Split the parameters some producers embed in the algorithm field, as in
+// `argon2id,v=19` or Sun md5-crypt's `md5,rounds=5000`, off the identifier.
+//
+// Identifiers only contain letters, digits, `-` and `_`. The first other
+// character separates the entries, and the first in each entry separates the
+// key from the value, so other dialects such as `argon2id;v=19` are split
+// too. A field without any `key=value` entry is left whole.
+fn split_algorithm(field: &str) -> (Hashes, Map<String, Value>) {
+    let is_id = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    let whole = |id: &str| Hashes::from_id(id).unwrap_or_else(|| Hashes::Unknown(id.to_string()));
+    let mut entries = Map::new();
+    let idx = match field.find(|c| !is_id(c)) {
+        Some(idx) => idx,
+        None => return (whole(field), entries),
+    };
+    let separator = field[idx..].chars().next().unwrap();
+    let rest = &field[idx + separator.len_utf8()..];
+    if !rest.contains(|c| !is_id(c) && c != separator) {
+        return (whole(field), entries);
+    }
+    for entry in rest.split(separator) {
+        let (key, value) = match entry.find(|c| !is_id(c)) {
+            Some(i) => {
+                let key_value = entry[i..].chars().next().unwrap();
+                (&entry[..i], &entry[i + key_value.len_utf8()..])
+            }
+            None => (entry, ""),
+        };
+        entries.insert(key.to_string(), Value::String(value.to_string()));
+    }
+    (whole(&field[..idx]), entries)
+}
+
+// The name under which argon2 parameters are passed to the serializer and
+// deserializer, so that the MCF ones can keep the version in a field of its
+// own. Other formats see a plain map.
+const VERSIONED_PARAMS: &str = "$serde_mcf::private::VersionedParams";
+
+// Parameters which start with a `v` version entry.
+struct VersionedParams<'a>(&'a Map<String, Value>);
+
+impl<'a> serde::Serialize for VersionedParams<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_newtype_struct(VERSIONED_PARAMS, self.0)
     }
+}
+
+// The parameters of an `McfHash`, along with the last entry which has no
+// value, if any. Such entries are kept with an empty value, but may instead
+// be bcrypt's cost as in `$2b$10$...`, which is only known once the
+// algorithm is.
+struct RawParams(Map<String, Value>, Option<String>);
+
+// Reads `RawParams`, which for argon2 may have the version in a field of its
+// own.
+struct RawParamsSeed(bool);
+
+impl<'de> serde::de::DeserializeSeed<'de> for RawParamsSeed {
+    type Value = RawParams;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<RawParams, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        struct RawParamsVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for RawParamsVisitor {
+            type Value = RawParams;
 
-    impl Into<McfHash> for BcryptHash {
-        fn into(self) -> McfHash {
-            let mut params = Map::<String, Value>::new();
-            params.insert("cost".to_string(), Value::Number(self.cost.into()));
-            McfHash {
-                algorithm: self.algorithm,
-                parameters: params,
-                salt: self.salthash.0,
-                hash: self.salthash.1,
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a map of parameters")
             }
+
+            fn visit_map<A>(self, mut access: A) -> std::result::Result<RawParams, A::Error>
+                where A: serde::de::MapAccess<'de>
+            {
+                let mut params = RawParams(Map::new(), None);
+                while let Some(key) = access.next_key::<String>()? {
+                    match access.next_value::<Option<Value>>()? {
+                        Some(value) => {
+                            params.0.insert(key, value);
+                        }
+                        None => {
+                            params.0.insert(key.clone(), Value::String(String::new()));
+                            params.1 = Some(key);
+                        }
+                    }
+                }
+                Ok(params)
+            }
+        }
+
+        if self.0 {
+            deserializer.deserialize_struct(VERSIONED_PARAMS, &[], RawParamsVisitor)
+        } else {
+            deserializer.deserialize_map(RawParamsVisitor)
         }
     }
 }
 
+pub mod legacy;
+pub use legacy::from_crypt_str;
+
 macro_rules! enum_hashes {
-    ($($hash:ident = $val:expr,)*) => (
-        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+    ($(#[$attr:meta])* $($hash:ident = $val:expr,)*) => (
+        $(#[$attr])*
+        #[derive(Clone, Debug, Eq, Hash, PartialEq)]
         pub enum Hashes {
             $(
-            #[serde(rename = $val)]
             $hash,
             )*
+            /// An identifier not known to this crate, kept verbatim so that it
+            /// round-trips.
+            Unknown(String),
         }
 
         impl Hashes {
+            /// Every known algorithm, in declaration order.
+            pub fn all() -> &'static [Hashes] {
+                &[$(Hashes::$hash,)*]
+            }
+
+            /// Look up a known algorithm by its identifier.
+            ///
+            /// Returns `None` for unknown identifiers, rather than
+            /// `Hashes::Unknown`.
             pub fn from_id(id: &str) -> Option<Hashes> {
                 match id {
                     $(
@@ -106,20 +909,77 @@ macro_rules! enum_hashes {
                 }
             }
 
-            pub fn to_id(&self) -> &'static str {
+            /// The identifier of the algorithm, as written in a hash.
+            ///
+            /// This borrows from `self` to cover `Hashes::Unknown`. Use
+            /// `known_id` where a `&'static str` is needed.
+            pub fn to_id(&self) -> &str {
+                match *self {
+                    Hashes::Unknown(ref id) => id,
+                    ref known => known.known_id().unwrap(),
+                }
+            }
+
+            /// The identifier of a known algorithm, or `None` for
+            /// `Hashes::Unknown`.
+            pub fn known_id(&self) -> Option<&'static str> {
+                match *self {
+                    $(
+                        Hashes::$hash => Some($val),
+                    )*
+                    Hashes::Unknown(_) => None,
+                }
+            }
+
+            // The position of a known algorithm in `all`, which is its
+            // variant index when serialized.
+            fn variant_index(&self) -> Option<u32> {
+                enum Index {
+                    $($hash,)*
+                }
+
                 match *self {
                     $(
-                        Hashes::$hash => $val,
+                        Hashes::$hash => Some(Index::$hash as u32),
                     )*
+                    Hashes::Unknown(_) => None,
                 }
             }
         }
     )
 }
 
-/// List of known algorithm identifiers.
-/// Source: https://passlib.readthedocs.io/en/stable/modular_crypt_format.html
+impl serde::Serialize for Hashes {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+        where S: serde::Serializer
+    {
+        match (self.known_id(), self.variant_index()) {
+            (Some(id), Some(index)) => serializer.serialize_unit_variant("Hashes", index, id),
+            _ => serializer.serialize_str(self.to_id()),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Hashes {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Hashes, D::Error>
+        where D: serde::Deserializer<'de>
+    {
+        use serde::de::Error;
+
+        // Parameters embedded in the identifier are only kept by `McfHash`,
+        // so they are rejected rather than lost.
+        let AlgorithmId(field) = AlgorithmId::deserialize(deserializer)?;
+        match split_algorithm(&field) {
+            (algorithm, ref embedded) if embedded.is_empty() => Ok(algorithm),
+            _ => Err(D::Error::custom(format!("unexpected parameters in algorithm `{}`", field))),
+        }
+    }
+}
+
 enum_hashes!{
+    /// List of known algorithm identifiers.
+    /// Source: https://passlib.readthedocs.io/en/stable/modular_crypt_format.html
+    DesCrypt = "des_crypt", // Traditional crypt(3), which has no identifier
     Md5Crypt = "1",
     Bcrypt = "2",
     Bcrypta = "2a",
@@ -135,6 +995,7 @@ enum_hashes!{
     AprMd5Crypt = "apr1", // Apache htdigest files
     Argon2i = "argon2i",
     Argon2d = "argon2d",
+    Argon2id = "argon2id",
     BcryptSha256 = "bcrypt-sha256", // Passlib-specific
     Phpassp = "P", // PHPass-based applicatoins
     Phpassh = "H", // PHPass-based applicatoins
@@ -144,11 +1005,94 @@ enum_hashes!{
     Scram = "scram", // Passlib-specific
     CtaPbkdf2Sha1 = "p5k2",
     Scrypt = "scrypt",  // Passlib-specific
+    Yescrypt = "y",
 
     Hmac = "hmac", // for libpasta
     Custom = "custom", // for any other purposes. fill details in params field
 }
 
+impl Hashes {
+    // Whether this is one of the bcrypt variants sharing the `$2x$cost$salthash`
+    // layout.
+    fn is_bcrypt(&self) -> bool {
+        matches!(*self,
+                 Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+                 Hashes::Bcryptb)
+    }
+
+    // Whether this is one of the argon2 variants, which may have the version
+    // in a field of its own.
+    fn is_argon2(&self) -> bool {
+        matches!(*self, Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id)
+    }
+
+    // The encodings of the salt and hash fields in `McfHash`'s string form.
+    fn field_encodings(&self) -> (FieldEncoding, FieldEncoding) {
+        match *self {
+            Hashes::Sha256Crypt | Hashes::Sha512Crypt => {
+                (FieldEncoding::Verbatim, FieldEncoding::Sha2Crypt)
+            }
+            Hashes::Md5Crypt | Hashes::AprMd5Crypt | Hashes::SunMd5Crypt => {
+                (FieldEncoding::Verbatim, FieldEncoding::Md5Crypt)
+            }
+            Hashes::Yescrypt => (FieldEncoding::Verbatim, FieldEncoding::Yescrypt),
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 => {
+                (FieldEncoding::Ab64, FieldEncoding::Ab64)
+            }
+            _ => (FieldEncoding::Base64, FieldEncoding::Base64),
+        }
+    }
+
+    // Whether this uses a verbatim salt and its own digest encoding, rather
+    // than base64, as the `crypt(3)` algorithms do.
+    fn is_crypt(&self) -> bool {
+        self.field_encodings().0 == FieldEncoding::Verbatim
+    }
+}
+
+// Standard unpadded base64, as a static for `FieldEncoding::base64`.
+static BASE64: &data_encoding::Encoding = &data_encoding::BASE64_NOPAD;
+
+impl FieldEncoding {
+    // The encoding, if this is one of the base64 variants whose fields can be
+    // decoded on their own.
+    fn base64(&self) -> Option<&'static data_encoding::Encoding> {
+        match *self {
+            FieldEncoding::Base64 => Some(BASE64),
+            FieldEncoding::Ab64 => Some(ab64::encoding()),
+            _ => None,
+        }
+    }
+}
+
+// How a salt or hash is written in `McfHash`'s string form.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FieldEncoding {
+    /// Standard unpadded base64.
+    Base64,
+    /// See `ab64`.
+    Ab64,
+    /// The bytes as they are, which must be UTF-8.
+    Verbatim,
+    /// See `sha2crypt`.
+    Sha2Crypt,
+    /// See `md5crypt`.
+    Md5Crypt,
+    /// See `yescrypt`.
+    Yescrypt,
+}
+
+impl<'a> TryFrom<&'a str> for Hashes {
+    type Error = errors::Error;
+
+    /// Look up a known algorithm by its identifier, as with `from_id`, but
+    /// with an `UnknownAlgorithm` error naming the identifier.
+    fn try_from(id: &'a str) -> errors::Result<Hashes> {
+        Hashes::from_id(id)
+            .ok_or_else(|| errors::ErrorKind::UnknownAlgorithm(id.to_string()).into())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -174,8 +1118,404 @@ mod test {
 
     }
 
+    #[test]
+    fn test_hashes_serde_ids() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Minimal {
+            algorithm: Hashes,
+            #[serde(with = "base64")]
+            hash: Vec<u8>,
+        }
+
+        assert!(Hashes::all().contains(&Hashes::BcryptMcf));
+        assert!(Hashes::all().contains(&Hashes::BcryptSha256));
+        for alg in Hashes::all() {
+            let minimal = Minimal {
+                algorithm: alg.clone(),
+                hash: b"hash".to_vec(),
+            };
+            let encoded = to_string(&minimal).unwrap();
+            assert_eq!(encoded, format!("${}$aGFzaA", alg.to_id()));
+            assert_eq!(from_str::<Minimal>(&encoded).unwrap(), minimal);
+            assert_eq!(Hashes::from_id(alg.to_id()).as_ref(), Some(alg));
+        }
+    }
+
+    #[test]
+    fn test_hashes_try_from() {
+        assert_eq!(Hashes::try_from("argon2id").unwrap(), Hashes::Argon2id);
+        assert_eq!(Hashes::try_from("2y").unwrap(), Hashes::Bcrypty);
+        let err = Hashes::try_from("nope").unwrap_err();
+        match *err.kind() {
+            errors::ErrorKind::UnknownAlgorithm(ref id) => assert_eq!(id, "nope"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!(err.to_string(), "unknown algorithm identifier `nope`");
+    }
+
+    #[test]
+    fn test_dashed_ids() {
+        let input = "$pbkdf2-sha256$29000$c2FsdA$aGFzaA";
+        let hash: McfHash = from_str(input).unwrap();
+        assert_eq!(hash.algorithm, Hashes::Pbkdf2Sha256);
+        assert!(hash.parameters.contains_key("29000"));
+        assert_eq!(to_string(&hash).unwrap(), input);
+
+        for alg in &[Hashes::Pbkdf2Sha512, Hashes::BcryptSha256, Hashes::BcryptMcf] {
+            let input = format!("${}$x=1$c2FsdA$aGFzaA", alg.to_id());
+            let hash: McfHash = from_str(&input).unwrap();
+            assert_eq!(&hash.algorithm, alg);
+            assert_eq!(to_string(&hash).unwrap(), input);
+        }
+
+        // The serde path agrees with `to_id` for every known algorithm.
+        for (index, alg) in Hashes::all().iter().enumerate() {
+            let id = to_string(alg).unwrap();
+            assert_eq!(id, format!("${}", alg.to_id()));
+            assert_eq!(&from_str::<Hashes>(&id).unwrap(), alg);
+            assert_eq!(alg.known_id(), Some(alg.to_id()));
+            assert_eq!(alg.variant_index(), Some(index as u32));
+        }
+    }
+
+    #[test]
+    fn test_unknown_algorithm() {
+        let input = "$futurealgo$x=1$c2FsdA$aGFzaA";
+        let hash: McfHash = from_str(input).unwrap();
+        assert_eq!(hash.algorithm, Hashes::Unknown("futurealgo".to_string()));
+        assert_eq!(hash.algorithm.to_id(), "futurealgo");
+        assert_eq!(hash.algorithm.known_id(), None);
+        assert_eq!(Hashes::from_id("futurealgo"), None);
+        assert_eq!(to_string(&hash).unwrap(), input);
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(serde_json::from_str::<McfHash>(&json).unwrap(), hash);
+        let known = serde_json::to_string(&Hashes::Argon2id).unwrap();
+        assert_eq!(known, "\"argon2id\"");
+        assert_eq!(serde_json::from_str::<Hashes>(&known).unwrap(), Hashes::Argon2id);
+    }
+
+    #[test]
+    fn test_alphabets() {
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let bcrypt_hash = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+        let sha_hash = "$5$rounds=5000$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5";
+        let sha_default = "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5";
+        let apr_hash = "$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/";
+
+        let argon: McfHash = from_str(argon_hash).unwrap();
+        assert_eq!(argon.salt, Some(b"somesalt".to_vec()));
+        assert_eq!(argon.hash.len(), 32);
+        let bcrypt: McfHash = from_str(bcrypt_hash).unwrap();
+        assert_eq!(bcrypt.hash,
+                   from_str::<legacy::BcryptHash>(bcrypt_hash).map(McfHash::from).unwrap().hash);
+
+        let sha: McfHash = from_str(sha_hash).unwrap();
+        let legacy: legacy::ShaCryptHash = from_str(sha_hash).unwrap();
+        assert_eq!(sha.salt, Some(b"saltstring".to_vec()));
+        assert_eq!(sha.hash, legacy.hash);
+        assert_eq!(to_string(&sha).unwrap(), sha_hash);
+
+        let default: McfHash = from_str(sha_default).unwrap();
+        assert!(default.parameters.is_empty());
+        assert_eq!((&default.salt, &default.hash), (&sha.salt, &sha.hash));
+        assert_eq!(to_string(&default).unwrap(), sha_default);
+
+        let apr: McfHash = from_str(apr_hash).unwrap();
+        assert_eq!(apr.salt, Some(b"r31.....".to_vec()));
+        assert_eq!(apr.hash.len(), 16);
+        assert_eq!(to_string(&apr).unwrap(), apr_hash);
+
+        let mut reused = argon.clone();
+        for &input in &[sha_hash, sha_default, apr_hash, bcrypt_hash, argon_hash] {
+            reused.parse_into(input).unwrap();
+            assert_eq!(reused, from_str::<McfHash>(input).unwrap());
+        }
+
+        let json = serde_json::to_string(&sha).unwrap();
+        assert_eq!(serde_json::from_str::<McfHash>(&json).unwrap(), sha);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let hash: McfHash = from_str("$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA").unwrap();
+        let json = hash.to_json().unwrap();
+        assert_eq!(json,
+                   serde_json::json!({
+                       "algorithm": "argon2i",
+                       "parameters": {"m": "65536", "t": "2", "p": "1"},
+                       "salt": "c29tZXNhbHQ",
+                       "hash": "aGFzaA",
+                   }));
+        assert_eq!(json, serde_json::to_value(&hash).unwrap());
+        assert_eq!(serde_json::from_value::<McfHash>(json).unwrap(), hash);
+
+        let sha: McfHash = from_str("$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5")
+            .unwrap();
+        assert_eq!(sha.to_json().unwrap()["salt"], "saltstring");
+        let nthash: McfHash = from_str("$3$$iEb36u6PsRetBr3YMLdYbA").unwrap();
+        assert!(nthash.to_json().unwrap().get("salt").is_none());
+    }
+
+    #[test]
+    fn test_accessors() {
+        let hash: McfHash = from_str("$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA").unwrap();
+        assert_eq!(hash.digest(), b"hash");
+        assert_eq!(hash.salt_bytes(), Some(&b"somesalt"[..]));
+        let nthash: McfHash = from_str("$3$$iEb36u6PsRetBr3YMLdYbA").unwrap();
+        assert_eq!(nthash.salt_bytes(), None);
+        assert_eq!(nthash.digest().len(), 16);
+    }
+
+    #[test]
+    fn test_map_parameters() {
+        let hash: McfHash = from_str("$scrypt$ln=16,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        let mapped = hash.clone().map_parameters(|key, value| match key {
+            "ln" => {
+                let ln = value.as_str()?.parse::<u32>().ok()?;
+                Some(("N".to_string(), Value::from(1u64 << ln)))
+            }
+            "p" => None,
+            _ => Some((key.to_string(), value)),
+        });
+        assert_eq!(to_string(&mapped).unwrap(), "$scrypt$N=65536,r=8$c2FsdA$aGFzaA");
+        assert_eq!(mapped.param_u64("N"), Some(65536));
+        assert_eq!((&mapped.algorithm, &mapped.salt, &mapped.hash),
+                   (&hash.algorithm, &hash.salt, &hash.hash));
+    }
+
+    #[test]
+    fn test_saltless() {
+        let nthash = "$3$$iEb36u6PsRetBr3YMLdYbA";
+        let hash: McfHash = from_str(nthash).unwrap();
+        assert_eq!(hash.algorithm, Hashes::BsdNtHash);
+        assert!(hash.parameters.is_empty());
+        assert_eq!(hash.salt, None);
+        assert_eq!(hash.hash.len(), 16);
+        assert_eq!(to_string(&hash).unwrap(), nthash);
+
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(serde_json::from_str::<McfHash>(&json).unwrap(), hash);
+
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let argon: McfHash = from_str(argon_hash).unwrap();
+        assert_eq!(argon.salt, Some(b"somesalt".to_vec()));
+        let json = serde_json::to_string(&argon).unwrap();
+        assert_eq!(serde_json::from_str::<McfHash>(&json).unwrap(), argon);
+
+        // An empty salt is not a missing one.
+        let empty_salt = "$argon2i$m=1$$aGFzaA";
+        let mut hash: McfHash = from_str(empty_salt).unwrap();
+        assert_eq!(hash.salt, Some(vec![]));
+        assert_eq!(to_string(&hash).unwrap(), empty_salt);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(serde_json::from_str::<McfHash>(&json).unwrap(), hash);
+        hash.parse_into("$argon2i$m=1$aGFzaA").unwrap();
+        assert_eq!(hash.salt, None);
+        hash.parse_into(empty_salt).unwrap();
+        assert_eq!(hash.salt, Some(vec![]));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_zeroize() {
+        use zeroize::{Zeroize, ZeroizeOnDrop};
+
+        fn wiped_on_drop<T: ZeroizeOnDrop>() {}
+        wiped_on_drop::<McfHash>();
+
+        let mut hash: McfHash = from_str("$argon2i$m=1,t=1,p=1$c29tZXNhbHQ$aGFzaA").unwrap();
+        hash.zeroize();
+        assert_eq!(hash.algorithm, Hashes::Argon2i);
+        assert_eq!(hash.parameters.len(), 3);
+        assert_eq!(hash.salt, None);
+        assert!(hash.hash.is_empty());
+    }
+
+    #[test]
+    fn test_bcrypt_generic() {
+        let bcrypt_hash = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+        let hash: McfHash = from_str(bcrypt_hash).unwrap();
+        assert_eq!(hash.algorithm, Hashes::Bcrypta);
+        assert_eq!(hash.parameters["cost"], Value::Number(10.into()));
+        assert_eq!(hash.salt.as_ref().map(Vec::len), Some(16));
+        assert_eq!(hash.hash.len(), 23);
+
+        let legacy: McfHash = from_str::<legacy::BcryptHash>(bcrypt_hash).unwrap().into();
+        assert_eq!(hash, legacy);
+
+        // Once written in the generic form, it parses back the same way.
+        let generic = to_string(&hash).unwrap();
+        assert!(generic.starts_with("$2a$cost=10$"));
+        let reparsed: McfHash = from_str(&generic).unwrap();
+        assert_eq!((&reparsed.salt, &reparsed.hash), (&hash.salt, &hash.hash));
+
+        let empty: McfHash = from_str("$argon2i$m=,t=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(empty.parameters["m"], Value::String(String::new()));
+    }
+
+    #[test]
+    fn test_parse_into() {
+        let vectors = ["$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                        $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc",
+                       "$3$$iEb36u6PsRetBr3YMLdYbA",
+                       "$argon2id,v=19$m=1,t=1,p=1$c2FsdA$aGFzaA",
+                       "$argon2id$v=19$m=1,t=1,p=1$c2FsdA$aGFzaA",
+                       "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe"];
+        let mut hash: McfHash = from_str(vectors[0]).unwrap();
+        for vector in &vectors {
+            hash.parse_into(vector).unwrap();
+            assert_eq!(hash, from_str::<McfHash>(vector).unwrap());
+        }
+        assert!(hash.parse_into("$argon2i$m=1$c2FsdA$!!").is_err());
+    }
+
+    #[test]
+    fn test_raw_fields() {
+        let bcrypt_hash = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+        let raw = McfHash::from_str_with_raw(bcrypt_hash).unwrap();
+        assert_eq!(raw.raw_salt_str(), Some("ckjEeyTD6estWyoofn4ERO"));
+        assert_eq!(raw.raw_hash_str(), "M9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe");
+        assert_eq!(raw.hash, from_str::<McfHash>(bcrypt_hash).unwrap());
+        // Written in the generic form, the salt is re-encoded differently.
+        assert!(!to_string(&raw.hash).unwrap().contains("ckjEeyTD6estWyoofn4ERO"));
+
+        let raw = McfHash::from_str_with_raw("$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!((raw.raw_salt_str(), raw.raw_hash_str()), (Some("c2FsdA"), "aGFzaA"));
+        let raw = McfHash::from_str_with_raw("$3$$iEb36u6PsRetBr3YMLdYbA").unwrap();
+        assert_eq!((raw.raw_salt_str(), raw.raw_hash_str()), (None, "iEb36u6PsRetBr3YMLdYbA"));
+        assert!(McfHash::from_str_with_raw("$argon2i$m=1$c2FsdA$!!").is_err());
+
+        let crypt = [("$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZF7HTCVY9",
+                      "saltstring",
+                      "5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZF7HTCVY9"),
+                     ("$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.\
+                       opqey6IcA",
+                      "saltstringsaltst",
+                      "3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA"),
+                     ("$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/",
+                      "r31.....",
+                      "HqJZimcKQFAMYayBlzkrA/"),
+                     ("$md5,rounds=5000$GUBv0xjJ$$mSwgIswdjlTY0YxV7HBVm0",
+                      "GUBv0xjJ$",
+                      "mSwgIswdjlTY0YxV7HBVm0")];
+        for &(input, salt, hash) in &crypt {
+            let raw = McfHash::from_str_with_raw(input).unwrap();
+            assert_eq!((raw.raw_salt_str(), raw.raw_hash_str()), (Some(salt), hash));
+            assert_eq!(raw.hash, from_str::<McfHash>(input).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_field_encodings() {
+        assert_eq!(Hashes::Argon2i.field_encodings(),
+                   (FieldEncoding::Base64, FieldEncoding::Base64));
+        assert_eq!(Hashes::SunMd5Crypt.field_encodings(),
+                   (FieldEncoding::Verbatim, FieldEncoding::Md5Crypt));
+
+        // Sun's md5-crypt has a verbatim salt, which isn't valid base64, and
+        // the md5-crypt digest alphabet. These are from Passlib's tests, and
+        // the salt includes the `$` before a `$$`.
+        let vectors = [("$md5$3UqYqndY$$6P.aaWOoucxxq.l00SS9k0", "3UqYqndY$", None),
+                       ("$md5$RPgLF6IJ$WTvAlUJ7MqH5xak2FMEwS/", "RPgLF6IJ", None),
+                       ("$md5,rounds=5000$GUBv0xjJ$$mSwgIswdjlTY0YxV7HBVm0",
+                        "GUBv0xjJ$",
+                        Some(5000))];
+        for &(input, salt, rounds) in &vectors {
+            let hash: McfHash = from_str(input).unwrap();
+            assert_eq!(hash.algorithm, Hashes::SunMd5Crypt);
+            assert_eq!(hash.salt, Some(salt.as_bytes().to_vec()));
+            assert_eq!(hash.param_u64("rounds"), rounds);
+            assert_eq!(hash.hash.len(), 16);
+            assert_eq!(to_string(&hash).unwrap(), input);
+        }
+        assert!(from_str::<McfHash>("$md5$3UqYqndY$c2FsdA").is_err());
+
+        // Passlib's pbkdf2 hashes use its adapted base64, with `.` for `+`.
+        // This is from the Passlib documentation.
+        assert_eq!(Hashes::Pbkdf2Sha256.field_encodings(),
+                   (FieldEncoding::Ab64, FieldEncoding::Ab64));
+        let input = "$pbkdf2-sha256$29000$9t7be09prfXee2/NOUeotQ\
+                     $yIPphVdF97u.XzygN1ToroW.pJoenSO2MPWQIaDuH7Y";
+        let hash: McfHash = from_str(input).unwrap();
+        let legacy: legacy::Pbkdf2Hash = from_str(input).unwrap();
+        assert_eq!(hash.salt.as_ref(), Some(&legacy.salt));
+        assert_eq!(hash.hash, legacy.hash);
+        assert_eq!(hash.hash.len(), 32);
+        assert_eq!(to_string(&hash).unwrap(), input);
+        assert!(hash.validate().is_ok());
+        let mut reused: McfHash = from_str("$argon2i$m=1$c2FsdA$aGFzaA").unwrap();
+        reused.parse_into(input).unwrap();
+        assert_eq!(reused, hash);
+        assert_eq!(hash.to_json().unwrap()["hash"], "yIPphVdF97u.XzygN1ToroW.pJoenSO2MPWQIaDuH7Y");
+    }
+
+    #[test]
+    fn test_huge_param() {
+        // One more than `u64::MAX`.
+        let input = "$scrypt$ln=18446744073709551616,r=8,p=1$c2FsdA$aGFzaA";
+        let hash: McfHash = from_str(input).unwrap();
+        assert_eq!(hash.parameters["ln"], Value::String("18446744073709551616".to_string()));
+        assert_eq!(to_string(&hash).unwrap(), input);
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn test_huge_json_param() {
+        let input = "$scrypt${\"ln\":18446744073709551616,\"r\":8}$c2FsdA$aGFzaA";
+        let hash: McfHash = from_str(input).unwrap();
+        assert_eq!(hash.parameters["ln"].to_string(), "18446744073709551616");
+        assert_eq!(to_string(&hash).unwrap(), "$scrypt$ln=18446744073709551616,r=8$c2FsdA$aGFzaA");
+        let options = ser::Options {
+            json_params: true,
+            ..Default::default()
+        };
+        assert_eq!(to_string_with_options(&hash, options).unwrap(), input);
+    }
+
+    #[test]
+    fn test_sun_md5_crypt() {
+        // Vectors from Passlib's documentation and tests.
+        let hash: McfHash = from_str("$md5,rounds=5000$GUBv0xjJ$$mSwgIswdjlTY0YxV7HBVm0").unwrap();
+        assert_eq!(hash.algorithm, Hashes::SunMd5Crypt);
+        assert_eq!(hash.parameters.len(), 1);
+        assert_eq!(hash.param_u64("rounds"), Some(5000));
+        // The `$` before the `$$` terminator is part of the salt.
+        assert_eq!(hash.salt, Some(b"GUBv0xjJ$".to_vec()));
+        assert_eq!(hash.hash.len(), 16);
+        assert_eq!(to_string(&hash).unwrap(), "$md5,rounds=5000$GUBv0xjJ$$mSwgIswdjlTY0YxV7HBVm0");
+
+        let hash: McfHash = from_str("$md5$3UqYqndY$$6P.aaWOoucxxq.l00SS9k0").unwrap();
+        assert!(hash.parameters.is_empty());
+        assert_eq!(hash.salt, Some(b"3UqYqndY$".to_vec()));
+        assert_eq!(to_string(&hash).unwrap(), "$md5$3UqYqndY$$6P.aaWOoucxxq.l00SS9k0");
+
+        let hash: McfHash = from_str("$md5,rounds=5000$GUBv0xjJ$mSwgIswdjlTY0YxV7HBVm0").unwrap();
+        assert_eq!(hash.salt, Some(b"GUBv0xjJ".to_vec()));
+        assert_eq!(to_string(&hash).unwrap(), "$md5,rounds=5000$GUBv0xjJ$mSwgIswdjlTY0YxV7HBVm0");
+
+        assert!(from_str::<McfHash>("$md5$3UqYqndY$x$6P.aaWOoucxxq.l00SS9k0").is_err());
+    }
+
+    #[test]
+    fn test_clone_eq() {
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let argon: McfHash = from_str(argon_hash).unwrap();
+        assert_eq!(argon.clone(), argon);
+        assert_eq!(from_str::<McfHash>(argon_hash).unwrap(), argon);
+
+        let other: McfHash = from_str("$argon2i$m=262144,p=1,t=3$c29tZXNhbHQ\
+                                       $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc")
+            .unwrap();
+        assert!(other != argon);
+    }
+
     #[test]
     fn test_trial_deserialize() {
+        #[allow(dead_code)]
         #[derive(Deserialize)]
         #[serde(untagged)]
         enum BcryptOrArgon {
@@ -188,37 +1528,29 @@ mod test {
         let bcrypt_hash = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
 
         let argon = {
-            match from_str::<McfHash>(argon_hash) {
-                Ok(v) => BcryptOrArgon::Argon(v),
+            match from_str::<legacy::BcryptHash>(argon_hash) {
+                Ok(v) => BcryptOrArgon::Bcrypt(v),
                 Err(_) => {
-                    let v = from_str::<legacy::BcryptHash>(argon_hash).unwrap();
-                    BcryptOrArgon::Bcrypt(v)
+                    let v = from_str::<McfHash>(argon_hash).unwrap();
+                    BcryptOrArgon::Argon(v)
                 }
             }
         };
 
 
 
-        assert!(if let BcryptOrArgon::Argon(_) = argon {
-            true
-        } else {
-            false
-        });
+        assert!(matches!(argon, BcryptOrArgon::Argon(_)));
 
         let bcrypt = {
-            match from_str::<McfHash>(bcrypt_hash) {
-                Ok(v) => BcryptOrArgon::Argon(v),
+            match from_str::<legacy::BcryptHash>(bcrypt_hash) {
+                Ok(v) => BcryptOrArgon::Bcrypt(v),
                 Err(_) => {
-                    let v = from_str::<legacy::BcryptHash>(bcrypt_hash).unwrap();
-                    BcryptOrArgon::Bcrypt(v)
+                    let v = from_str::<McfHash>(bcrypt_hash).unwrap();
+                    BcryptOrArgon::Argon(v)
                 }
             }
         };
 
-        assert!(if let BcryptOrArgon::Bcrypt(_) = bcrypt {
-            true
-        } else {
-            false
-        });
+        assert!(matches!(bcrypt, BcryptOrArgon::Bcrypt(_)));
     }
 }
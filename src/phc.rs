@@ -0,0 +1,162 @@
+//! Conversion between `McfHash` and the PHC string format.
+//!
+//! The PHC format is defined at
+//! https://github.com/P-H-C/phc-string-format/blob/master/phc-sf-spec.md and
+//! looks like `$<id>[$v=<version>][$<param>=<value>(,<param>=<value>)*][$<salt>[$<hash>]]`.
+//!
+//! The main differences from the MCF layout used by `McfHash` are that the
+//! version lives in its own segment, and that only a handful of algorithms
+//! have a PHC identifier at all.
+
+use data_encoding::BASE64_NOPAD;
+use std::convert::TryFrom;
+
+use errors::*;
+use prelude::*;
+use super::{Hashes, Map, McfHash, Value};
+
+impl Hashes {
+    /// Whether this algorithm has a representation in the PHC string format.
+    fn is_phc(&self) -> bool {
+        matches!(*self,
+                 Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id | Hashes::Scrypt |
+                 Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512)
+    }
+}
+
+// Render a parameter value as it appears in the string form.
+pub fn value_to_string(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => s.clone(),
+        ref v => v.to_string(),
+    }
+}
+
+impl McfHash {
+    /// Convert the hash into a PHC-format string.
+    ///
+    /// A `v` parameter, if present, is emitted as the separate version segment.
+    /// Returns an error for algorithms which have no PHC equivalent.
+    pub fn to_phc_string(&self) -> Result<String> {
+        if !self.algorithm.is_phc() {
+            return Err(ErrorKind::UnsupportedAlgorithm(self.algorithm.to_id().to_string()).into());
+        }
+        let mut out = format!("${}", self.algorithm.to_id());
+        if let Some(v) = self.parameters.get("v") {
+            out.push_str("$v=");
+            out.push_str(&value_to_string(v));
+        }
+        let params = self.parameters
+            .iter()
+            .filter(|&(k, _)| k != "v")
+            .map(|(k, v)| format!("{}={}", k, value_to_string(v)))
+            .collect::<Vec<_>>();
+        if !params.is_empty() {
+            out.push('$');
+            out.push_str(&params.join(","));
+        }
+        if self.salt.is_some() || !self.hash.is_empty() {
+            out.push('$');
+            out.push_str(&BASE64_NOPAD.encode(self.salt.as_ref().map_or(&[][..], |s| s)));
+        }
+        if !self.hash.is_empty() {
+            out.push('$');
+            out.push_str(&BASE64_NOPAD.encode(&self.hash));
+        }
+        Ok(out)
+    }
+
+    /// Parse a PHC-format string into an `McfHash`.
+    ///
+    /// The version segment is stored as the `v` parameter, ahead of the
+    /// remaining parameters. Parameter values are kept as strings, matching
+    /// `from_str`.
+    pub fn from_phc_str(input: &str) -> Result<McfHash> {
+        let mut fields = input.split('$');
+        if fields.next() != Some("") {
+            return Err("PHC string must start with `$`".into());
+        }
+        let id = fields.next().ok_or("missing algorithm identifier")?;
+        let algorithm = Hashes::try_from(id)?;
+        if !algorithm.is_phc() {
+            return Err(ErrorKind::UnsupportedAlgorithm(algorithm.to_id().to_string()).into());
+        }
+
+        let mut fields = fields.peekable();
+        let mut parameters = Map::new();
+        if let Some(version) = fields.peek().and_then(|f| f.strip_prefix("v=")) {
+            parameters.insert("v".to_string(), Value::String(version.to_string()));
+            fields.next();
+        }
+        if fields.peek().is_some_and(|f| f.contains('=')) {
+            for param in fields.next().unwrap().split(',') {
+                let mut kv = param.splitn(2, '=');
+                let key = kv.next().unwrap();
+                let value = kv.next().ok_or_else(|| format!("parameter `{}` has no value", key))?;
+                parameters.insert(key.to_string(), Value::String(value.to_string()));
+            }
+        }
+        let salt = match fields.next() {
+            Some(s) => Some(BASE64_NOPAD.decode(s.as_bytes())?),
+            None => None,
+        };
+        let hash = match fields.next() {
+            Some(h) => BASE64_NOPAD.decode(h.as_bytes())?,
+            None => Vec::new(),
+        };
+        if fields.next().is_some() {
+            return Err("too many fields in PHC string".into());
+        }
+
+        Ok(McfHash {
+            algorithm,
+            parameters,
+            salt,
+            hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use {from_str, legacy};
+
+    // Reference vectors from the Argon2 reference implementation
+    // (password "password", salt "somesalt").
+    const ARGON2I: &str = "$argon2i$v=19$m=65536,t=2,p=1$c29tZXNhbHQ\
+                           $wWKIMhR9lyDFvRz9YTZweHKfbftvj+qf+YFY4NeBbtA";
+    const ARGON2ID: &str = "$argon2id$v=19$m=65536,t=2,p=1$c29tZXNhbHQ\
+                            $CTFhFdXPJO1aFaMaO6Mm5c8y7cJHAph8ArZWb2GRPPc";
+
+    #[test]
+    fn test_phc_roundtrip() {
+        for &vector in &[ARGON2I, ARGON2ID] {
+            let hash = McfHash::from_phc_str(vector).unwrap();
+            assert_eq!(hash.parameters["v"], Value::String("19".to_string()));
+            assert_eq!(hash.salt, Some(b"somesalt".to_vec()));
+            assert_eq!(hash.hash.len(), 32);
+            assert_eq!(hash.to_phc_string().unwrap(), vector);
+        }
+        assert_eq!(McfHash::from_phc_str(ARGON2ID).unwrap().algorithm,
+                   Hashes::Argon2id);
+    }
+
+    #[test]
+    fn test_mcf_to_phc() {
+        let mcf = "$argon2i$v=19,m=65536,t=2,p=1$c29tZXNhbHQ\
+                   $wWKIMhR9lyDFvRz9YTZweHKfbftvj+qf+YFY4NeBbtA";
+        let hash: McfHash = from_str(mcf).unwrap();
+        assert_eq!(hash.to_phc_string().unwrap(), ARGON2I);
+    }
+
+    #[test]
+    fn test_phc_unsupported() {
+        let bcrypt = "$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe";
+        let bcrypt: legacy::BcryptHash = from_str(bcrypt).unwrap();
+        let hash: McfHash = bcrypt.into();
+        assert!(hash.to_phc_string().is_err());
+        assert!(McfHash::from_phc_str("$2a$cost=10$c29tZXNhbHQ").is_err());
+        assert!(McfHash::from_phc_str("argon2i$m=1$c29tZXNhbHQ").is_err());
+    }
+}
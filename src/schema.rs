@@ -0,0 +1,863 @@
+//! Per-algorithm knowledge of which parameters a hash is expected to carry.
+
+use std::convert::TryFrom;
+
+use errors::*;
+use prelude::*;
+use super::{to_string, Hashes, Map, McfHash, Value};
+
+/// The cost of a hash, normalized across algorithms.
+///
+/// Each dimension is `None` when it does not apply to the algorithm, or the
+/// parameters needed to compute it are missing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CostSummary {
+    /// Memory used, in KiB.
+    pub memory_kib: Option<u32>,
+    /// Number of iterations of the core function.
+    pub iterations: Option<u32>,
+    /// Degree of parallelism.
+    pub parallelism: Option<u32>,
+}
+
+/// How suitable an algorithm is for storing passwords today.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SecurityStatus {
+    /// Broken or trivially brute-forced; rehash as soon as possible.
+    Insecure,
+    /// Weak by modern standards, or superseded by a fixed variant.
+    Deprecated,
+    /// Fine with suitable parameters, though not the first choice for new
+    /// hashes.
+    Acceptable,
+    /// Recommended for new hashes.
+    Recommended,
+}
+
+/// A group of algorithms sharing the same construction, differing only in
+/// version or encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Family {
+    /// `argon2i`, `argon2d` and `argon2id`.
+    Argon2,
+    /// All the `$2*$` variants, as well as `bcrypt-sha256`.
+    Bcrypt,
+    /// Passlib's `pbkdf2` variants, and Cryptacular's `p5k2`.
+    Pbkdf2,
+    /// `scrypt`.
+    Scrypt,
+    /// `yescrypt`.
+    Yescrypt,
+    /// `sha256crypt` and `sha512crypt`.
+    ShaCrypt,
+    /// `md5crypt`, Apache's `apr1` and Sun's `md5`.
+    Md5Crypt,
+    /// The PHPass `$P$` and `$H$` hashes.
+    Phpass,
+    /// Traditional DES `crypt(3)`.
+    DesCrypt,
+    /// Anything else, which has no related variants.
+    Other,
+}
+
+impl Hashes {
+    /// The family of related algorithms this one belongs to.
+    pub fn family(&self) -> Family {
+        match *self {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => Family::Argon2,
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb | Hashes::BcryptMcf | Hashes::BcryptSha256 => Family::Bcrypt,
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 |
+            Hashes::CtaPbkdf2Sha1 => Family::Pbkdf2,
+            Hashes::Scrypt => Family::Scrypt,
+            Hashes::Yescrypt => Family::Yescrypt,
+            Hashes::Sha256Crypt | Hashes::Sha512Crypt => Family::ShaCrypt,
+            Hashes::Md5Crypt | Hashes::AprMd5Crypt | Hashes::SunMd5Crypt => Family::Md5Crypt,
+            Hashes::Phpassp | Hashes::Phpassh => Family::Phpass,
+            Hashes::DesCrypt => Family::DesCrypt,
+            Hashes::BsdNtHash | Hashes::Sha1Crypt | Hashes::Scram | Hashes::Hmac |
+            Hashes::Custom | Hashes::Unknown(_) => Family::Other,
+        }
+    }
+
+    /// This crate's opinion of how suitable the algorithm is for storing
+    /// passwords, irrespective of its parameters.
+    ///
+    /// `Hmac` and `Custom` wrap other algorithms, and are `Acceptable`.
+    /// Unknown algorithms can't be assessed, so are taken to be `Insecure`.
+    pub fn security_status(&self) -> SecurityStatus {
+        match *self {
+            Hashes::Argon2id | Hashes::Scrypt | Hashes::Yescrypt => SecurityStatus::Recommended,
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Bcrypt | Hashes::Bcrypta |
+            Hashes::Bcrypty | Hashes::Bcryptb | Hashes::BcryptMcf | Hashes::BcryptSha256 |
+            Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 | Hashes::Sha256Crypt |
+            Hashes::Sha512Crypt | Hashes::Scram | Hashes::Hmac | Hashes::Custom => {
+                SecurityStatus::Acceptable
+            }
+            Hashes::Bcryptx | Hashes::Pbkdf2Sha1 | Hashes::CtaPbkdf2Sha1 | Hashes::Sha1Crypt |
+            Hashes::Phpassp | Hashes::Phpassh => SecurityStatus::Deprecated,
+            Hashes::DesCrypt | Hashes::Md5Crypt | Hashes::AprMd5Crypt | Hashes::SunMd5Crypt |
+            Hashes::BsdNtHash | Hashes::Unknown(_) => SecurityStatus::Insecure,
+        }
+    }
+
+    /// The newest, recommended variant in the same family as this
+    /// algorithm, for upgrading hashes without changing algorithm family.
+    ///
+    /// For example, any argon2 variant gives `Argon2id`, and any bcrypt
+    /// variant gives `Bcryptb`. Algorithms without a newer variant return
+    /// themselves.
+    pub fn newest_in_family(&self) -> Hashes {
+        match *self {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => Hashes::Argon2id,
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb => Hashes::Bcryptb,
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 => {
+                Hashes::Pbkdf2Sha512
+            }
+            Hashes::Sha256Crypt | Hashes::Sha512Crypt => Hashes::Sha512Crypt,
+            ref alg => alg.clone(),
+        }
+    }
+
+    /// The parameters this algorithm requires, each of which must be an
+    /// unsigned integer. Each entry lists the accepted names for a single
+    /// parameter.
+    fn required_params(&self) -> &'static [&'static [&'static str]] {
+        match *self {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => &[&["m"], &["t"], &["p"]],
+            Hashes::Scrypt => &[&["ln", "N"], &["r"], &["p"]],
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 => {
+                &[&["rounds", "i"]]
+            }
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb | Hashes::BcryptMcf => &[&["cost"]],
+            _ => &[],
+        }
+    }
+
+    /// The accepted salt lengths in bytes, as an inclusive range, or `None`
+    /// if this algorithm has no salt. Algorithms without fixed lengths accept
+    /// anything up to `usize::MAX`.
+    fn salt_len(&self) -> Option<(usize, usize)> {
+        match *self {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => Some((8, usize::MAX)),
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb | Hashes::BcryptMcf => Some((16, 16)),
+            Hashes::Sha256Crypt | Hashes::Sha512Crypt => Some((0, 16)),
+            Hashes::Md5Crypt | Hashes::AprMd5Crypt => Some((0, 8)),
+            Hashes::DesCrypt => Some((2, 2)),
+            Hashes::BsdNtHash => None,
+            _ => Some((0, usize::MAX)),
+        }
+    }
+
+    /// The accepted hash lengths in bytes, as an inclusive range.
+    fn hash_len(&self) -> (usize, usize) {
+        match *self {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => (4, usize::MAX),
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb | Hashes::BcryptMcf => (23, 23),
+            Hashes::Pbkdf2Sha1 => (20, 20),
+            Hashes::Pbkdf2Sha256 | Hashes::Sha256Crypt | Hashes::Yescrypt => (32, 32),
+            Hashes::Pbkdf2Sha512 | Hashes::Sha512Crypt => (64, 64),
+            Hashes::Md5Crypt | Hashes::AprMd5Crypt | Hashes::BsdNtHash => (16, 16),
+            Hashes::DesCrypt => (8, 8),
+            _ => (1, usize::MAX),
+        }
+    }
+
+    /// All parameters known to be unsigned integers for this algorithm.
+    fn numeric_params(&self) -> &'static [&'static str] {
+        match *self {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => &["m", "t", "p", "v"],
+            Hashes::Scrypt => &["ln", "N", "r", "p"],
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 => &["rounds", "i"],
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb | Hashes::BcryptMcf => &["cost"],
+            Hashes::Sha256Crypt | Hashes::Sha512Crypt | Hashes::SunMd5Crypt => &["rounds"],
+            _ => &[],
+        }
+    }
+}
+
+// Read a parameter value as an unsigned integer, either from a JSON number
+// or from the string form produced by `from_str`.
+fn value_as_u64(value: &Value) -> Option<u64> {
+    match *value {
+        Value::Number(ref n) => n.as_u64(),
+        Value::String(ref s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+// The alphabet of crypt's base64, used by yescrypt.
+const ITOA64: &str = "./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+// Decode yescrypt's parameter field, such as `j9T`, into `(N, r, p)`.
+//
+// The field holds variable-length integers: the flags, the base 2 logarithm
+// of `N`, `r`, and then optionally `p`, which is otherwise 1.
+fn yescrypt_params(field: &str) -> Option<(u32, u32, u32)> {
+    let mut src = field.as_bytes();
+    let _flags = decode64_uint32(&mut src, 0)?;
+    let n = 1u32.checked_shl(decode64_uint32(&mut src, 1)?)?;
+    let r = decode64_uint32(&mut src, 1)?;
+    // Further parameters are only present if flagged, starting with `p`.
+    let p = if src.is_empty() {
+        1
+    } else {
+        let have = decode64_uint32(&mut src, 1)?;
+        if have & 1 != 0 { decode64_uint32(&mut src, 2)? } else { 1 }
+    };
+    Some((n, r, p))
+}
+
+// The variable-length integer encoding of yescrypt's `decode64_uint32`.
+fn decode64_uint32(src: &mut &[u8], min: u32) -> Option<u32> {
+    let mut next = || {
+        let (&first, rest) = src.split_first()?;
+        *src = rest;
+        ITOA64.bytes().position(|b| b == first).map(|c| c as u64)
+    };
+    // The first character gives both the length and the high bits.
+    let c = next()?;
+    let (mut start, mut end, mut bits) = (0, 47, 0);
+    let mut dst = u64::from(min);
+    while c > end {
+        dst += (end + 1 - start) << bits;
+        start = end + 1;
+        end = start + (62 - end) / 2;
+        bits += 6;
+    }
+    dst += (c - start) << bits;
+    while bits > 0 {
+        bits -= 6;
+        dst += next()? << bits;
+    }
+    u32::try_from(dst).ok()
+}
+
+impl McfHash {
+    /// The family of the hash's algorithm, as given by `Hashes::family`.
+    pub fn algorithm_family(&self) -> Family {
+        self.algorithm.family()
+    }
+
+    /// Get the parameter `key` as an unsigned integer.
+    ///
+    /// Accepts both numbers and numeric strings. Returns `None` if the
+    /// parameter is missing or not an unsigned integer.
+    pub fn param_u64(&self, key: &str) -> Option<u64> {
+        self.parameters.get(key).and_then(value_as_u64)
+    }
+
+    /// Get the parameter `key` in its string form.
+    ///
+    /// Numbers and booleans are converted to their textual form. Returns
+    /// `None` if the parameter is missing, or is an array, object or null.
+    pub fn param_str(&self, key: &str) -> Option<String> {
+        match *self.parameters.get(key)? {
+            Value::String(ref s) => Some(s.clone()),
+            Value::Number(ref n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Get the parameter `key` as a boolean.
+    ///
+    /// Accepts booleans, and the strings `true`, `false`, `1` and `0`. Flags
+    /// written with `BoolStyle::Flag`, such as `fips` in `m=1,fips`, are
+    /// parsed as an empty string, which is read as `true`. Returns `None` if
+    /// the parameter is missing or not a boolean.
+    pub fn param_bool(&self, key: &str) -> Option<bool> {
+        match *self.parameters.get(key)? {
+            Value::Bool(b) => Some(b),
+            Value::String(ref s) if s == "true" || s == "1" || s.is_empty() => Some(true),
+            Value::String(ref s) if s == "false" || s == "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Iterate over the parameters in the algorithm's canonical order, such as
+    /// `m`, `t`, `p` for argon2, or `ln`, `r`, `p` for scrypt.
+    ///
+    /// Any other parameters follow in the map's order, which is the only
+    /// order for algorithms without a canonical one.
+    pub fn params_ordered(&self) -> impl Iterator<Item = (&str, &Value)> {
+        let known: Vec<&str> = self.algorithm
+            .required_params()
+            .iter()
+            .filter_map(|names| names.iter().find(|n| self.parameters.contains_key(**n)))
+            .cloned()
+            .collect();
+        let rest = self.parameters
+            .iter()
+            .map(|(k, v)| (k.as_str(), v))
+            .filter(|&(k, _)| !known.contains(&k))
+            .collect::<Vec<_>>();
+        known.into_iter()
+            .map(move |k| (k, &self.parameters[k]))
+            .chain(rest)
+    }
+
+    /// Normalize the hash, so that equivalent hashes compare equal and are
+    /// written identically.
+    ///
+    /// This:
+    ///
+    ///  * orders the parameters as `params_ordered` does, with any others
+    ///    sorted by name,
+    ///  * stores the algorithm's numeric parameters as numbers, so that
+    ///    `rounds=05000` and `rounds=5000` agree,
+    ///  * replaces bcrypt's `2a` and `2y` tags with `2b`, which compute the
+    ///    same hash. Some old `2a` implementations differ for passwords over
+    ///    255 bytes.
+    ///
+    /// The salt and hash are stored decoded, so differences in how they were
+    /// encoded never reach the output, and bcrypt is always written in the
+    /// generic `$2b$cost=N$salt$hash` form.
+    ///
+    /// Distinct algorithms are not unified, even within a family, such as
+    /// `argon2i` and `argon2id`, or bcrypt's `2x` variant. Nor are default
+    /// parameters filled in, so `$5$salt$...` and `$5$rounds=5000$salt$...`
+    /// stay different.
+    pub fn canonicalize(&mut self) {
+        if matches!(self.algorithm, Hashes::Bcrypta | Hashes::Bcrypty) {
+            self.algorithm = Hashes::Bcryptb;
+        }
+        let mut params = ::std::mem::take(&mut self.parameters);
+        let mut ordered = Map::new();
+        for names in self.algorithm.required_params() {
+            if let Some((key, value)) =
+                names.iter().find_map(|n| params.remove(*n).map(|v| (n.to_string(), v))) {
+                ordered.insert(key, value);
+            }
+        }
+        let mut rest = params.into_iter().collect::<Vec<_>>();
+        rest.sort_by(|a, b| a.0.cmp(&b.0));
+        ordered.extend(rest);
+
+        let numeric = self.algorithm.numeric_params();
+        for (key, value) in ordered.iter_mut() {
+            if numeric.contains(&key.as_str()) {
+                if let Some(n) = value_as_u64(value) {
+                    *value = Value::Number(n.into());
+                }
+            }
+        }
+        self.parameters = ordered;
+    }
+
+    /// Write the hash in the canonical form given by `canonicalize`, for
+    /// deduplicating and comparing hashes.
+    pub fn canonical_string(&self) -> Result<String> {
+        let mut hash = self.clone();
+        hash.canonicalize();
+        to_string(&hash)
+    }
+
+    /// Summarize the cost of this hash in algorithm-independent terms.
+    ///
+    /// For example, a bcrypt `cost` of 10 is 1024 iterations, and scrypt with
+    /// `ln=14,r=8` uses 2^14 * 128 * 8 bytes of memory.
+    ///
+    /// Costs written without a key are read too, such as the rounds of
+    /// Passlib's `$pbkdf2-sha256$29000$...` and the cost of version 1
+    /// `$bcrypt-sha256$2a,12$...` hashes, as are yescrypt's encoded
+    /// parameters. Algorithms with a fixed cost, such as md5-crypt, report
+    /// it.
+    pub fn cost_summary(&self) -> CostSummary {
+        let param = |key: &str| self.param_u64(key).and_then(|v| u32::try_from(v).ok());
+        let bare = || self.bare_param().and_then(|p| p.parse().ok());
+        let pow2 = |exp: u32| 1u32.checked_shl(exp);
+        let iterations = |iterations| CostSummary { iterations, ..CostSummary::default() };
+        match self.algorithm {
+            Hashes::Argon2i | Hashes::Argon2d | Hashes::Argon2id => CostSummary {
+                memory_kib: param("m"),
+                iterations: param("t"),
+                parallelism: param("p"),
+            },
+            Hashes::Scrypt => {
+                let n = param("ln")
+                    .and_then(pow2)
+                    .or_else(|| param("N").filter(|n| n.is_power_of_two()));
+                CostSummary {
+                    // Each block is 128 * r bytes, and N blocks are used.
+                    memory_kib: n.and_then(|n| n.checked_mul(param("r")?)).map(|nr| nr / 8),
+                    iterations: n,
+                    parallelism: param("p"),
+                }
+            }
+            Hashes::Yescrypt => {
+                let (n, r, p) = match self.bare_param().and_then(yescrypt_params) {
+                    Some(params) => params,
+                    None => return CostSummary::default(),
+                };
+                CostSummary {
+                    memory_kib: n.checked_mul(r).map(|nr| nr / 8),
+                    iterations: Some(n),
+                    parallelism: Some(p),
+                }
+            }
+            Hashes::Bcrypt | Hashes::Bcrypta | Hashes::Bcryptx | Hashes::Bcrypty |
+            Hashes::Bcryptb | Hashes::BcryptMcf => iterations(param("cost").and_then(pow2)),
+            // Version 2 has the cost as `r`, and version 1 as the last entry.
+            Hashes::BcryptSha256 => iterations(param("r").or_else(bare).and_then(pow2)),
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 => {
+                iterations(param("rounds").or_else(|| param("i")).or_else(bare))
+            }
+            // Cryptacular writes the rounds in hexadecimal.
+            Hashes::CtaPbkdf2Sha1 => {
+                iterations(self.bare_param().and_then(|p| u32::from_str_radix(p, 16).ok()))
+            }
+            // Rounds are optional for these, and default to 5000.
+            Hashes::Sha256Crypt | Hashes::Sha512Crypt => {
+                iterations(param("rounds").or(Some(5000)))
+            }
+            Hashes::Md5Crypt | Hashes::AprMd5Crypt => iterations(Some(1000)),
+            // Sun's rounds are in addition to a basic 4096.
+            Hashes::SunMd5Crypt => {
+                iterations(param("rounds").unwrap_or(0).checked_add(4096))
+            }
+            Hashes::DesCrypt => iterations(Some(25)),
+            // PHPass packs its cost into the field with the salt and hash,
+            // which are read as one, so the cost is not known.
+            Hashes::Phpassp | Hashes::Phpassh | Hashes::BsdNtHash | Hashes::Sha1Crypt |
+            Hashes::Scram | Hashes::Hmac | Hashes::Custom | Hashes::Unknown(_) => {
+                CostSummary::default()
+            }
+        }
+    }
+
+    // The key of the last parameter, if it has no value, as with costs
+    // written on their own.
+    fn bare_param(&self) -> Option<&str> {
+        match self.parameters.iter().next_back() {
+            Some((key, Value::String(value))) if value.is_empty() => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Whether this hash should be rehashed to match `target`, such as a hash
+    /// freshly made with the current policy.
+    ///
+    /// Hashes from a different family than the target always need
+    /// rehashing. Within a family, the costs given by `cost_summary` are
+    /// compared, and a rehash is needed if any is lower than the target's, or
+    /// is missing where the target has one. Differences of variant within a
+    /// family are not considered; see `Hashes::newest_in_family`.
+    pub fn needs_rehash(&self, target: &McfHash) -> bool {
+        if self.algorithm.family() != target.algorithm.family() {
+            return true;
+        }
+        let weaker = |ours: Option<u32>, theirs: Option<u32>| match (ours, theirs) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(ours), Some(theirs)) => ours < theirs,
+        };
+        let (ours, theirs) = (self.cost_summary(), target.cost_summary());
+        weaker(ours.memory_kib, theirs.memory_kib) ||
+        weaker(ours.iterations, theirs.iterations) ||
+        weaker(ours.parallelism, theirs.parallelism)
+    }
+
+    /// Check that the parameters required by `self.algorithm` are present
+    /// and numeric.
+    ///
+    /// Parsing is deliberately lenient, so this is a separate step for
+    /// consumers who want to reject hashes no implementation could use.
+    pub fn validate(&self) -> Result<()> {
+        let mut missing = Vec::new();
+        let mut invalid = Vec::new();
+        // Passlib writes the pbkdf2 rounds on their own, as in
+        // `$pbkdf2-sha256$29000$...`.
+        let bare_rounds = match self.algorithm {
+            Hashes::Pbkdf2Sha1 | Hashes::Pbkdf2Sha256 | Hashes::Pbkdf2Sha512 => self.bare_param(),
+            _ => None,
+        };
+        for names in self.algorithm.required_params() {
+            match names.iter().filter_map(|n| self.parameters.get(*n).map(|v| (n, v))).next() {
+                None if names[0] == "rounds" && bare_rounds.is_some() => {
+                    let rounds = bare_rounds.unwrap();
+                    if rounds.parse::<u64>().is_err() {
+                        invalid.push(rounds.to_string());
+                    }
+                }
+                None => missing.push(names[0].to_string()),
+                Some((name, value)) if value_as_u64(value).is_none() => invalid.push(name.to_string()),
+                Some(_) => {}
+            }
+        }
+        if missing.is_empty() && invalid.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorKind::InvalidParameters(missing, invalid).into())
+        }
+    }
+
+    /// Build a hash from its parts, checking they are consistent.
+    ///
+    /// As well as the checks made by `validate`, this checks the salt and
+    /// hash lengths are ones the algorithm can produce, including that the
+    /// salt is present exactly when the algorithm uses one.
+    pub fn try_from_components(algorithm: Hashes,
+                               parameters: Map<String, Value>,
+                               salt: Option<Vec<u8>>,
+                               hash: Vec<u8>)
+                               -> Result<McfHash> {
+        let in_range = |len: usize, (min, max): (usize, usize)| min <= len && len <= max;
+        match (algorithm.salt_len(), salt.as_ref()) {
+            (Some(range), Some(salt)) if !in_range(salt.len(), range) => {
+                return Err(format!("invalid salt length {} for `{}`",
+                                   salt.len(),
+                                   algorithm.to_id())
+                    .into())
+            }
+            (Some(_), None) => {
+                return Err(format!("`{}` requires a salt", algorithm.to_id()).into())
+            }
+            (None, Some(_)) => {
+                return Err(format!("`{}` does not use a salt", algorithm.to_id()).into())
+            }
+            _ => {}
+        }
+        if !in_range(hash.len(), algorithm.hash_len()) {
+            return Err(format!("invalid hash length {} for `{}`", hash.len(), algorithm.to_id())
+                .into());
+        }
+        let hash = McfHash {
+            algorithm,
+            parameters,
+            salt,
+            hash,
+        };
+        hash.validate()?;
+        Ok(hash)
+    }
+
+    /// Convert the parameters `self.algorithm` defines as numeric into
+    /// `Value::Number`s.
+    ///
+    /// `from_str` keeps all parameter values as strings. Unknown parameters,
+    /// and values which do not parse as integers, are left unchanged.
+    pub fn coerce_parameters(&mut self) {
+        for name in self.algorithm.numeric_params() {
+            if let Some(value) = self.parameters.get_mut(*name) {
+                let n = match *value {
+                    Value::String(ref s) => s.parse::<u64>().ok(),
+                    _ => None,
+                };
+                if let Some(n) = n {
+                    *value = Value::Number(n.into());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use from_str;
+
+    #[test]
+    fn test_validate() {
+        let valid: McfHash = from_str("$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                                       $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc")
+            .unwrap();
+        assert!(valid.validate().is_ok());
+
+        let broken: McfHash = from_str("$argon2i$m=lots,x=1$c29tZXNhbHQ\
+                                        $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc")
+            .unwrap();
+        match *broken.validate().unwrap_err().kind() {
+            ErrorKind::InvalidParameters(ref missing, ref invalid) => {
+                assert_eq!(missing, &["t", "p"]);
+                assert_eq!(invalid, &["m"]);
+            }
+            ref e => panic!("unexpected error: {}", e),
+        }
+
+        for input in &["$pbkdf2-sha256$29000$c2FsdA$aGFzaA",
+                       "$pbkdf2-sha256$i=29000$c2FsdA$aGFzaA",
+                       "$scrypt$N=1024,r=8,p=1$c2FsdA$aGFzaA",
+                       "$scrypt$ln=10,r=8,p=1$c2FsdA$aGFzaA"] {
+            assert!(from_str::<McfHash>(input).unwrap().validate().is_ok(), "{}", input);
+        }
+        let bare: McfHash = from_str("$pbkdf2-sha256$many$c2FsdA$aGFzaA").unwrap();
+        match *bare.validate().unwrap_err().kind() {
+            ErrorKind::InvalidParameters(ref missing, ref invalid) => {
+                assert!(missing.is_empty());
+                assert_eq!(invalid, &["many"]);
+            }
+            ref e => panic!("unexpected error: {}", e),
+        }
+        let scrypt: McfHash = from_str("$scrypt$r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        match *scrypt.validate().unwrap_err().kind() {
+            ErrorKind::InvalidParameters(ref missing, _) => assert_eq!(missing, &["ln"]),
+            ref e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_coerce_parameters() {
+        let mut hash: McfHash = from_str("$argon2i$m=65536,t=2,p=1,data=123$c29tZXNhbHQ\
+                                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc")
+            .unwrap();
+        hash.coerce_parameters();
+        assert_eq!(hash.parameters["m"], Value::Number(65536.into()));
+        assert_eq!(hash.parameters["t"], Value::Number(2.into()));
+        assert_eq!(hash.parameters["data"], Value::String("123".to_string()));
+        assert!(hash.validate().is_ok());
+    }
+
+    #[test]
+    fn test_typed_params() {
+        let mut hash: McfHash = from_str("$argon2i$m=65536,t=2,p=x$c29tZXNhbHQ$aGFzaA").unwrap();
+        assert_eq!(hash.param_u64("m"), Some(65536));
+        assert_eq!(hash.param_u64("p"), None);
+        assert_eq!(hash.param_u64("missing"), None);
+        assert_eq!(hash.param_str("p"), Some("x".to_string()));
+        assert_eq!(hash.param_str("missing"), None);
+
+        hash.coerce_parameters();
+        assert_eq!(hash.param_u64("t"), Some(2));
+        assert_eq!(hash.param_str("t"), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_cost_summary() {
+        let argon: McfHash = from_str("$argon2id$v=19,m=65536,t=2,p=4$c29tZXNhbHQ$aGFzaA").unwrap();
+        assert_eq!(argon.cost_summary(),
+                   CostSummary {
+                       memory_kib: Some(65536),
+                       iterations: Some(2),
+                       parallelism: Some(4),
+                   });
+
+        let bcrypt: McfHash = from_str("$2b$cost=10$c29tZXNhbHQ$aGFzaA").unwrap();
+        assert_eq!(bcrypt.cost_summary(),
+                   CostSummary {
+                       memory_kib: None,
+                       iterations: Some(1024),
+                       parallelism: None,
+                   });
+
+        let scrypt: McfHash = from_str("$scrypt$ln=14,r=8,p=1$c29tZXNhbHQ$aGFzaA").unwrap();
+        assert_eq!(scrypt.cost_summary(),
+                   CostSummary {
+                       memory_kib: Some(16384),
+                       iterations: Some(16384),
+                       parallelism: Some(1),
+                   });
+        let scrypt: McfHash = from_str("$scrypt$N=1024,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(scrypt.cost_summary(),
+                   CostSummary {
+                       memory_kib: Some(1024),
+                       iterations: Some(1024),
+                       parallelism: Some(1),
+                   });
+        let scrypt: McfHash = from_str("$scrypt$N=1000,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(scrypt.cost_summary().iterations, None);
+
+        let yescrypt: McfHash = from_str("$y$j9T$F5Jx5fExrKuPp53xLKQ..1$X3DX6M94c7o.9agCG9G317\
+                                          fhZg9SqC.5i5rd.RhAtQ7")
+            .unwrap();
+        assert_eq!(yescrypt.cost_summary(),
+                   CostSummary {
+                       memory_kib: Some(16384),
+                       iterations: Some(4096),
+                       parallelism: Some(1),
+                   });
+    }
+
+    #[test]
+    fn test_cost_summary_iterations() {
+        let iterations = |hash: &str| from_str::<McfHash>(hash).unwrap().cost_summary().iterations;
+        assert_eq!(iterations("$pbkdf2-sha256$29000$c2FsdA$aGFzaA"), Some(29000));
+        assert_eq!(iterations("$pbkdf2-sha256$i=29000$c2FsdA$aGFzaA"), Some(29000));
+        assert_eq!(iterations("$pbkdf2$rounds=1000$c2FsdA$aGFzaA"), Some(1000));
+        assert_eq!(iterations("$p5k2$2710$oX9ZZOM3$aGFzaA"), Some(10000));
+        assert_eq!(iterations("$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.\
+                               opqey6IcA"),
+                   Some(10000));
+        assert_eq!(iterations("$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJu\
+                               esI68u4OTLiBFdcbYEdFCoEOfaS35inz1"),
+                   Some(5000));
+        assert_eq!(iterations("$1$5pZSV9va$azfrPr6af3Fc7dLblQXVa0"), Some(1000));
+        assert_eq!(iterations("$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/"), Some(1000));
+        assert_eq!(iterations("$md5,rounds=5000$GUBv0xjJ$$mSwgIswdjlTY0YxV7HBVm0"), Some(9096));
+
+        let mut hash: McfHash = from_str("$bcrypt-sha256$v=2,t=2b,r=12$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(hash.cost_summary().iterations, Some(4096));
+        hash.parameters = from_str("$2a,12").unwrap();
+        assert_eq!(hash.cost_summary().iterations, Some(4096));
+
+        hash.algorithm = Hashes::DesCrypt;
+        assert_eq!(hash.cost_summary().iterations, Some(25));
+        hash.algorithm = Hashes::Phpassp;
+        assert_eq!(hash.cost_summary(), CostSummary::default());
+    }
+
+    #[test]
+    fn test_newest_in_family() {
+        assert_eq!(Hashes::Argon2i.newest_in_family(), Hashes::Argon2id);
+        assert_eq!(Hashes::Argon2id.newest_in_family(), Hashes::Argon2id);
+        assert_eq!(Hashes::Bcrypta.newest_in_family(), Hashes::Bcryptb);
+        assert_eq!(Hashes::Bcrypt.newest_in_family(), Hashes::Bcryptb);
+        assert_eq!(Hashes::Pbkdf2Sha1.newest_in_family(), Hashes::Pbkdf2Sha512);
+        assert_eq!(Hashes::Scrypt.newest_in_family(), Hashes::Scrypt);
+    }
+
+    #[test]
+    fn test_family() {
+        for alg in &[Hashes::Bcrypt, Hashes::Bcrypta, Hashes::Bcryptx, Hashes::Bcrypty,
+                     Hashes::Bcryptb, Hashes::BcryptSha256] {
+            assert_eq!(alg.family(), Family::Bcrypt);
+        }
+        assert_eq!(Hashes::Argon2d.family(), Family::Argon2);
+        assert_eq!(Hashes::CtaPbkdf2Sha1.family(), Family::Pbkdf2);
+        assert_eq!(Hashes::Hmac.family(), Family::Other);
+
+        let hash: McfHash = from_str("$argon2i$m=512,t=3,p=1$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(hash.algorithm_family(), Family::Argon2);
+    }
+
+    #[test]
+    fn test_params_ordered() {
+        let hash: McfHash = from_str("$argon2id$v=19,p=1,t=2,m=65536$c2FsdA$aGFzaA").unwrap();
+        let keys = hash.params_ordered().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys, ["m", "t", "p", "v"]);
+        assert_eq!(hash.params_ordered().next(),
+                   Some(("m", &Value::String("65536".to_string()))));
+
+        let hash: McfHash = from_str("$custom$b=1,a=2$c2FsdA$aGFzaA").unwrap();
+        let keys = hash.params_ordered().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(keys, ["b", "a"]);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let equivalent = [
+            ("$argon2id$v=19,p=1,t=2,m=65536$c2FsdA$aGFzaA",
+             "$argon2id$m=065536,t=2,p=1,v=19$c2FsdA$aGFzaA"),
+            ("$2a$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe",
+             "$2y$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.aqRdCMN/Oe"),
+            ("$custom$b=1,a=2$c2FsdA$aGFzaA", "$custom$a=2,b=1$c2FsdA$aGFzaA"),
+        ];
+        for &(a, b) in &equivalent {
+            let (mut a, mut b): (McfHash, McfHash) = (from_str(a).unwrap(), from_str(b).unwrap());
+            assert_eq!(a.canonical_string().unwrap(), b.canonical_string().unwrap());
+            a.canonicalize();
+            b.canonicalize();
+            assert_eq!(a, b);
+        }
+
+        let hash: McfHash = from_str(equivalent[0].1).unwrap();
+        assert_eq!(hash.canonical_string().unwrap(),
+                   "$argon2id$v=19$m=65536,t=2,p=1$c2FsdA$aGFzaA");
+
+        // The generic bcrypt form agrees with the packed one.
+        let packed: McfHash = from_str(equivalent[1].0).unwrap();
+        let generic: McfHash = from_str(&packed.canonical_string().unwrap()).unwrap();
+        assert_eq!(generic.algorithm, Hashes::Bcryptb);
+        assert_eq!(generic.canonical_string().unwrap(), packed.canonical_string().unwrap());
+
+        // Different algorithms stay different.
+        let argon2i: McfHash = from_str("$argon2i$m=65536,t=2,p=1$c2FsdA$aGFzaA").unwrap();
+        let argon2id: McfHash = from_str("$argon2id$m=65536,t=2,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(argon2i.canonical_string().unwrap() != argon2id.canonical_string().unwrap());
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let bcrypt10: McfHash = from_str("$2b$10$ckjEeyTD6estWyoofn4EROM9Ik2PqVcfcrepX.uGp6.\
+                                          aqRdCMN/Oe")
+            .unwrap();
+        let mut bcrypt12 = bcrypt10.clone();
+        bcrypt12.parameters.insert("cost".to_string(), Value::Number(12.into()));
+        assert!(bcrypt10.needs_rehash(&bcrypt12));
+        assert!(!bcrypt12.needs_rehash(&bcrypt10));
+        assert!(!bcrypt10.needs_rehash(&bcrypt10));
+
+        let argon: McfHash = from_str("$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA").unwrap();
+        let target: McfHash = from_str("$argon2id$m=65536,t=2,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(!argon.needs_rehash(&target));
+        let target: McfHash = from_str("$argon2id$m=65536,t=3,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(argon.needs_rehash(&target));
+
+        assert!(bcrypt12.needs_rehash(&argon));
+
+        let pbkdf2: McfHash = from_str("$pbkdf2-sha256$1000$c2FsdA$aGFzaA").unwrap();
+        let target: McfHash = from_str("$pbkdf2-sha256$29000$c2FsdA$aGFzaA").unwrap();
+        assert!(pbkdf2.needs_rehash(&target));
+        assert!(!target.needs_rehash(&pbkdf2));
+
+        let scrypt: McfHash = from_str("$scrypt$N=1024,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        let target: McfHash = from_str("$scrypt$N=16384,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(scrypt.needs_rehash(&target));
+        let target: McfHash = from_str("$scrypt$ln=10,r=8,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(!scrypt.needs_rehash(&target));
+
+        let cta: McfHash = from_str("$p5k2$3e8$c2FsdA$aGFzaA").unwrap();
+        let target: McfHash = from_str("$p5k2$2710$c2FsdA$aGFzaA").unwrap();
+        assert!(cta.needs_rehash(&target));
+        assert!(!target.needs_rehash(&cta));
+
+        let v1: McfHash = from_str("$bcrypt-sha256$2a,10$c2FsdA$aGFzaA").unwrap();
+        let v2: McfHash = from_str("$bcrypt-sha256$v=2,t=2b,r=12$c2FsdA$aGFzaA").unwrap();
+        assert!(v1.needs_rehash(&v2));
+        assert!(!v2.needs_rehash(&v1));
+    }
+
+    #[test]
+    fn test_try_from_components() {
+        let mut params = Map::new();
+        for &(k, v) in &[("m", 65536), ("t", 2), ("p", 1)] {
+            params.insert(k.to_string(), Value::Number(v.into()));
+        }
+        let salt = b"somesalt".to_vec();
+        let hash = vec![0; 32];
+
+        let valid = McfHash::try_from_components(Hashes::Argon2id,
+                                                 params.clone(),
+                                                 Some(salt.clone()),
+                                                 hash.clone())
+            .unwrap();
+        assert_eq!(valid.algorithm, Hashes::Argon2id);
+
+        // Salt too short.
+        assert!(McfHash::try_from_components(Hashes::Argon2id,
+                                             params.clone(),
+                                             Some(b"salt".to_vec()),
+                                             hash.clone())
+            .is_err());
+        // No salt at all.
+        assert!(McfHash::try_from_components(Hashes::Argon2id, params.clone(), None, hash.clone())
+            .is_err());
+        // Missing `t`.
+        let mut missing = params.clone();
+        missing.remove("t");
+        match *McfHash::try_from_components(Hashes::Argon2id, missing, Some(salt.clone()), hash)
+            .unwrap_err()
+            .kind() {
+            ErrorKind::InvalidParameters(ref missing, _) => assert_eq!(missing, &["t"]),
+            ref e => panic!("unexpected error: {}", e),
+        }
+        // Wrong digest length for pbkdf2-sha256.
+        let mut rounds = Map::new();
+        rounds.insert("rounds".to_string(), Value::Number(29000.into()));
+        assert!(McfHash::try_from_components(Hashes::Pbkdf2Sha256, rounds, Some(salt), vec![0; 20])
+            .is_err());
+    }
+
+    #[test]
+    fn test_security_status() {
+        assert_eq!(Hashes::Md5Crypt.security_status(), SecurityStatus::Insecure);
+        assert_eq!(Hashes::Bcryptb.security_status(), SecurityStatus::Acceptable);
+        assert_eq!(Hashes::Argon2id.security_status(), SecurityStatus::Recommended);
+        assert!(Hashes::Pbkdf2Sha1.security_status() < SecurityStatus::Acceptable);
+    }
+}
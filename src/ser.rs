@@ -1,29 +1,429 @@
 use serde::ser::*;
 use serde::ser;
-use std::fmt::Display;
-use std::io::Write;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+#[cfg(feature = "std")]
+use std::io::{self, BufWriter};
+use std::str;
+
+use serde_json;
 
 use errors::*;
 use errors::Error;
+use prelude::*;
+use Dialect;
+
+/// The output of the serializer.
+///
+/// With the `std` feature, this is implemented for every `io::Write`.
+/// Without it, it is implemented for `Vec<u8>`, and `to_string_in` writes to
+/// any `fmt::Write`.
+#[cfg(feature = "std")]
+pub use std::io::Write;
+
+/// The output of the serializer.
+///
+/// With the `std` feature, this is `io::Write`. Without it, it is implemented
+/// for `Vec<u8>`, and `to_string_in` writes to any `fmt::Write`.
+#[cfg(not(feature = "std"))]
+pub trait Write {
+    /// Write all of `bytes`.
+    fn write_all(&mut self, bytes: &[u8]) -> fmt::Result;
+
+    /// Write formatted output, for the `write!` macro.
+    fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
+        // Forwards the formatted pieces to `write_all`.
+        struct Adapter<'a, W: 'a + ?Sized>(&'a mut W);
+
+        impl<'a, W: Write + ?Sized> fmt::Write for Adapter<'a, W> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0.write_all(s.as_bytes())
+            }
+        }
+
+        fmt::write(&mut Adapter(self), args)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for Vec<u8> {
+    fn write_all(&mut self, bytes: &[u8]) -> fmt::Result {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write_all(&mut self, bytes: &[u8]) -> fmt::Result {
+        (**self).write_all(bytes)
+    }
+}
+
+/// Options controlling the output of the serializer.
+///
+/// The defaults reproduce parsed hashes verbatim.
+///
+/// More options may be added, so outside this crate, start from
+/// `Options::default()` and set the fields to change.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Options {
+    /// Strip leading zeros from integer-valued map values, so `rounds=05000`
+    /// is written as `rounds=5000`.
+    ///
+    /// Parameters parsed with `from_str` keep their original text, which is
+    /// what gets written when this is off.
+    pub normalize_numbers: bool,
+    /// A character written after each hash, such as `'\n'` for writing
+    /// `/etc/shadow`-style files. Defaults to none.
+    pub terminator: Option<char>,
+    /// Write maps, such as the `McfHash` parameters, as a JSON object like
+    /// `{"m":65536,"t":3}` instead of the `key=value,...` form.
+    ///
+    /// The JSON must not contain a `$`, which would end the field early.
+    pub json_params: bool,
+    /// How booleans are written.
+    pub bools: BoolStyle,
+    /// The character written between fields. Defaults to `$`.
+    ///
+    /// Any other character takes the place of `dialect.field`.
+    #[deprecated(note = "use `dialect.field` instead")]
+    pub delimiter: char,
+    /// The characters written between fields, map entries, and keys and
+    /// values. Defaults to `Dialect::mcf()`, which uses `$`, `,` and `=`.
+    pub dialect: Dialect,
+    /// Write map entries sorted by key, rather than in iteration order.
+    ///
+    /// This is for reproducible output, such as test fixtures. The iteration
+    /// order of a `HashMap` differs from one map to the next, so parameters
+    /// held in one are written in an arbitrary order unless this is set, or
+    /// a `BTreeMap` is used instead. Some algorithms define an order for
+    /// their parameters, so it is off by default.
+    pub sort_map_keys: bool,
+    /// Leave out struct fields which are empty maps, along with their
+    /// delimiter, so a `McfHash` without parameters is written as
+    /// `$3$salt$hash` rather than `$3$$salt$hash`.
+    ///
+    /// This is for verifiers which reject the empty field. `from_str` reads
+    /// such hashes as having the salt in the parameters field, so it is off
+    /// by default.
+    pub skip_empty_params: bool,
+    /// Wrap map values containing a comma in double quotes, as in
+    /// `note="a,b,c"`, so they can be read back by a deserializer with
+    /// `McfDeserializer::quoted_values`. Quoted values cannot contain `"`.
+    ///
+    /// No standard hash has such values, so this is off by default.
+    pub quote_values: bool,
+    /// Write sequences and tuples consisting only of `u8`s, such as `Vec<u8>`
+    /// and `[u8; N]`, as unpadded base64 instead of comma-separated numbers.
+    ///
+    /// Serde passes `[u8; N]` to the serializer as a tuple, so tuples such as
+    /// `(u8, u8)` are written as base64 too, and then cannot be read back.
+    /// Byte arrays are read back from base64 with `serde_bytes` or the
+    /// `base64` module. Off by default.
+    pub base64_bytes: bool,
+}
+
+impl Default for Options {
+    #[allow(deprecated)]
+    fn default() -> Self {
+        Options {
+            normalize_numbers: false,
+            terminator: None,
+            json_params: false,
+            bools: BoolStyle::default(),
+            delimiter: '$',
+            dialect: Dialect::mcf(),
+            sort_map_keys: false,
+            skip_empty_params: false,
+            quote_values: false,
+            base64_bytes: false,
+        }
+    }
+}
+
+/// How the serializer writes booleans.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BoolStyle {
+    /// As `true` and `false`. This is the default.
+    #[default]
+    Words,
+    /// As `1` and `0`.
+    Numeric,
+    /// As a bare `key` in maps when true, and omitted entirely when false.
+    /// Booleans outside of maps are written as words.
+    ///
+    /// `McfHash` reads a bare key back as an empty string, since a bare key
+    /// may also be a value such as Passlib's rounds, so use
+    /// `McfHash::param_bool` to read flags. Empty strings are written as
+    /// flags too, so the hash is written back the same way.
+    Flag,
+}
 
 /// Serializer for producing MCF-style hashes.
-pub struct McfSerializer<W: Write>(W);
+///
+/// A single serializer can be reused to write many hashes to the same writer
+/// with `serialize_entry`.
+pub struct McfSerializer<W: Write> {
+    writer: W,
+    options: Options,
+    annotate: bool,
+    separator: String,
+}
+
+impl<W: Write> McfSerializer<W> {
+    /// Create a new serializer writing to `writer`, with default options.
+    pub fn new(writer: W) -> Self {
+        McfSerializer::with_options(writer, Options::default())
+    }
+
+    /// Create a new serializer writing to `writer`, with the given options.
+    pub fn with_options(writer: W, options: Options) -> Self {
+        McfSerializer {
+            writer,
+            options,
+            annotate: false,
+            separator: "\n".to_string(),
+        }
+    }
+
+    /// Set the separator written after each `serialize_entry`. Defaults to a
+    /// newline.
+    pub fn separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Write a complete hash, including the leading `$` and any terminator,
+    /// followed by the separator.
+    pub fn serialize_entry<S: Serialize>(&mut self, value: &S) -> Result<()> {
+        self.write_hash(value)?;
+        self.writer.write_all(self.separator.as_bytes()).map_err(|e| e.into())
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // Write a complete hash, from the leading `$` to the terminator. Every
+    // function writing a hash goes through here.
+    fn write_hash<S: ?Sized + Serialize>(&mut self, value: &S) -> Result<()> {
+        self.write_delimiter()?;
+        value.serialize(&mut *self)?;
+        match self.options.terminator {
+            Some(terminator) => self.write_char(terminator),
+            None => Ok(()),
+        }
+    }
+
+    // Write argon2 parameters with the `v` entry in a field of its own, as in
+    // `v=19$m=65536,t=2,p=1`.
+    fn serialize_versioned<S: ?Sized + Serialize>(&mut self, value: &S) -> Result<()> {
+        let mut params = match serde_json::to_value(value) {
+            Ok(serde_json::Value::Object(params)) => params,
+            _ => return value.serialize(&mut *self),
+        };
+        let version = match params.shift_remove("v") {
+            Some(version) => version,
+            None => return params.serialize(&mut *self),
+        };
+        let mut map = self.serialize_map(Some(1))?;
+        map.serialize_entry("v", &version)?;
+        SerializeMap::end(map)?;
+        self.write_delimiter()?;
+        params.serialize(&mut *self)
+    }
 
-impl<'a, W: Write> McfSerializer<W> {
-    fn new(writer: W) -> Self {
-        McfSerializer(writer)
+    // Write the name of the field about to be serialized, when annotating.
+    fn annotate_field(&mut self, key: &'static str) -> Result<()> {
+        if self.annotate {
+            self.write(format!("[{}]", key))?;
+        }
+        Ok(())
     }
 
     fn write<T: AsRef<[u8]>>(&mut self, input: T) -> Result<()> {
-        self.0.write_all(input.as_ref()).map_err(|e| e.into())
+        self.writer.write_all(input.as_ref()).map_err(|e| e.into())
+    }
+
+    fn write_char(&mut self, c: char) -> Result<()> {
+        let mut buf = [0; 4];
+        self.write(c.encode_utf8(&mut buf))
+    }
+
+    #[allow(deprecated)]
+    fn write_delimiter(&mut self) -> Result<()> {
+        let field = match self.options.delimiter {
+            '$' => self.options.dialect.field,
+            delimiter => delimiter,
+        };
+        self.write_char(field)
+    }
+
+    // Write the separator between map entries or sequence elements.
+    fn write_entry_separator(&mut self) -> Result<()> {
+        let entry = self.options.dialect.entry;
+        self.write_char(entry)
+    }
+
+    // Write the separator between a map key and its value.
+    fn write_key_separator(&mut self) -> Result<()> {
+        let key_value = self.options.dialect.key_value;
+        self.write_char(key_value)
+    }
+
+    fn write_display<T: Display>(&mut self, value: T) -> Result<()> {
+        write!(self.writer, "{}", value).map_err(|e| e.into())
+    }
+
+    // `Display` never uses exponent notation for floats, so the output is
+    // plain decimal and parses back as written. NaN and the infinities have
+    // no such form, and are rejected.
+    fn write_float<T: Display>(&mut self, value: T, finite: bool) -> Result<()> {
+        if !finite {
+            return Err(format!("cannot serialize non-finite float `{}`", value).into());
+        }
+        self.write_display(value)
+    }
+
+    fn write_bool(&mut self, value: bool) -> Result<()> {
+        match (self.options.bools, value) {
+            (BoolStyle::Numeric, true) => self.write("1"),
+            (BoolStyle::Numeric, false) => self.write("0"),
+            _ => self.write_display(value),
+        }
+    }
+
+    // Write a serialized map value, applying any normalization.
+    fn write_value(&mut self, value: &str) -> Result<()> {
+        if self.options.normalize_numbers && !value.is_empty() &&
+           value.bytes().all(|b| b.is_ascii_digit()) {
+            let trimmed = value.trim_start_matches('0');
+            self.write(if trimmed.is_empty() { "0" } else { trimmed })
+        } else {
+            self.write(value)
+        }
+    }
+}
+
+/// Writes many hashes to a writer, one per line, through a `BufWriter`.
+///
+/// This is for bulk exports, such as dumping a credential database, where
+/// calling `to_string` for each record would allocate every time.
+#[cfg(feature = "std")]
+pub struct McfWriter<W: Write> {
+    ser: McfSerializer<BufWriter<W>>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> McfWriter<W> {
+    /// Create a new writer, with default options.
+    pub fn new(writer: W) -> Self {
+        McfWriter::with_options(writer, Options::default())
+    }
+
+    /// Create a new writer, with the given options. Each record ends with
+    /// the `terminator`, or a newline if there is none.
+    pub fn with_options(writer: W, mut options: Options) -> Self {
+        options.terminator = options.terminator.or(Some('\n'));
+        let ser = McfSerializer::with_options(BufWriter::new(writer), options).separator("");
+        McfWriter { ser }
+    }
+
+    /// Write `value` as a hash, including the leading `$`, followed by the
+    /// terminator.
+    ///
+    /// On error, part of the record may already have been written.
+    pub fn write<S: Serialize>(&mut self, value: &S) -> Result<()> {
+        self.ser.serialize_entry(value)
+    }
+
+    /// Flush the buffered output to the underlying writer.
+    pub fn flush(&mut self) -> Result<()> {
+        self.ser.writer.flush().map_err(|e| e.into())
+    }
+
+    /// Flush the buffered output, and unwrap the underlying writer.
+    pub fn into_inner(self) -> Result<W> {
+        self.ser.into_inner().into_inner().map_err(|e| e.into_error().into())
     }
 }
 
 /// Serialize object to a MCF-style hash.
 pub fn to_string<S: Serialize>(s: &S) -> Result<String> {
+    to_string_with_options(s, Options::default())
+}
+
+/// Serialize object to a MCF-style hash, using the supplied `Options`.
+pub fn to_string_with_options<S: Serialize>(s: &S, options: Options) -> Result<String> {
+    let mut buf = Vec::new();
+    McfSerializer::with_options(&mut buf, options).write_hash(s)?;
+    Ok(String::from_utf8(buf).unwrap())
+}
+
+/// Serialize object as a MCF-style hash into the writer.
+#[cfg(feature = "std")]
+pub fn to_writer<W: Write, S: Serialize>(writer: W, s: &S) -> Result<()> {
+    to_writer_with_options(writer, s, Options::default())
+}
+
+/// Serialize object as a MCF-style hash into the writer, using the supplied
+/// `Options`. Set the `terminator` to `'\n'` to write one hash per line.
+#[cfg(feature = "std")]
+pub fn to_writer_with_options<W: Write, S: Serialize>(writer: W,
+                                                      s: &S,
+                                                      options: Options)
+                                                      -> Result<()> {
+    McfSerializer::with_options(writer, options).write_hash(s)
+}
+
+/// Serialize object as a MCF-style hash into a `fmt::Write`, such as a
+/// fixed-capacity `arrayvec::ArrayString` to avoid allocating.
+///
+/// If the buffer runs out of space, an error is returned and the buffer holds
+/// a truncated hash.
+pub fn to_string_in<W: fmt::Write, S: Serialize>(buf: &mut W, s: &S) -> Result<()> {
+    McfSerializer::new(FmtWriter(buf)).write_hash(s)
+}
+
+// Adapts a `fmt::Write` to the `Write` used by the serializer, which only
+// ever writes whole strings.
+struct FmtWriter<'a, W: 'a + fmt::Write>(&'a mut W);
+
+#[cfg(not(feature = "std"))]
+impl<'a, W: fmt::Write> Write for FmtWriter<'a, W> {
+    fn write_all(&mut self, bytes: &[u8]) -> fmt::Result {
+        self.0.write_str(str::from_utf8(bytes).map_err(|_| fmt::Error)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a, W: fmt::Write> Write for FmtWriter<'a, W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let s = str::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.0
+            .write_str(s)
+            .map_err(|_| io::Error::new(io::ErrorKind::WriteZero, "buffer is full"))?;
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Serialize object to a MCF-style hash, with each struct field prefixed by
+/// its name in brackets, e.g. `$[algorithm]argon2i$[params]m=...`.
+///
+/// This is a debugging aid for checking which field ends up in which
+/// segment; the output cannot be deserialized.
+pub fn to_annotated_string<S: Serialize>(s: &S) -> Result<String> {
     let mut buf = Vec::new();
-    buf.write_all(b"$")?;
-    s.serialize(&mut McfSerializer::new(&mut buf))?;
+    let mut ser = McfSerializer::new(&mut buf);
+    ser.annotate = true;
+    ser.write_hash(s)?;
     Ok(String::from_utf8(buf).unwrap())
 }
 
@@ -38,8 +438,7 @@ macro_rules! serialize_as_string {
     ($($ty:ty => $meth:ident,)*) => {
         $(
             fn $meth(self, v: $ty) -> Result<Self::Ok> {
-                // Ok(v.to_string())
-                Ok(v.to_string())
+                self.ser.write_display(v)
             }
         )*
     };
@@ -48,31 +447,42 @@ macro_rules! serialize_as_string {
 impl<'a, W: Write> Serializer for &'a mut McfSerializer<W> {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = McfSeq<'a, W>;
-    type SerializeTuple = McfSeq<'a, W>;
-    type SerializeTupleStruct = Self;
+    type SerializeSeq = McfList<'a, W>;
+    type SerializeTuple = McfList<'a, W>;
+    type SerializeTupleStruct = McfSeq<'a, W>;
     type SerializeTupleVariant = Self;
-    type SerializeMap = McfSeq<'a, W>;
+    type SerializeMap = McfMap<'a, W>;
     type SerializeStruct = McfSeq<'a, W>;
     type SerializeStructVariant = Self;
 
     serialize_as_string!{
         mcf
-        bool => serialize_bool,
         u8  => serialize_u8,
         u16 => serialize_u16,
         u32 => serialize_u32,
         u64 => serialize_u64,
+        u128 => serialize_u128,
         i8  => serialize_i8,
         i16 => serialize_i16,
         i32 => serialize_i32,
         i64 => serialize_i64,
-        f32 => serialize_f32,
-        f64 => serialize_f64,
+        i128 => serialize_i128,
         char => serialize_char,
         &str => serialize_str,
     }
 
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
+        self.write_float(v, v.is_finite())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
+        self.write_float(v, v.is_finite())
+    }
+
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        self.write_bool(v)
+    }
 
     fn serialize_bytes(self, value: &[u8]) -> Result<Self::Ok> {
         super::base64::serialize(&value, self)
@@ -80,12 +490,12 @@ impl<'a, W: Write> Serializer for &'a mut McfSerializer<W> {
 
     /// Returns an error.
     fn serialize_unit(self) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("unit").into())
     }
 
     /// Returns an error.
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("unit_struct").into())
     }
 
     fn serialize_unit_variant(self,
@@ -97,9 +507,12 @@ impl<'a, W: Write> Serializer for &'a mut McfSerializer<W> {
     }
 
     fn serialize_newtype_struct<T: ?Sized + ser::Serialize>(self,
-                                                            _name: &'static str,
+                                                            name: &'static str,
                                                             value: &T)
                                                             -> Result<Self::Ok> {
+        if name == ::VERSIONED_PARAMS {
+            return self.serialize_versioned(value);
+        }
         value.serialize(self)
     }
 
@@ -110,34 +523,36 @@ impl<'a, W: Write> Serializer for &'a mut McfSerializer<W> {
                                                              value: &T)
                                                              -> Result<Self::Ok> {
         self.write(variant)?;
+        self.write_delimiter()?;
         value.serialize(self)
     }
 
     /// Returns an error.
     fn serialize_none(self) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("none").into())
     }
 
     /// Returns an error.
     fn serialize_some<T: ?Sized + ser::Serialize>(self, _value: &T) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("some").into())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Ok(McfSeq(self, false))
+        Ok(McfList::new(self))
     }
 
 
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Ok(McfSeq(self, false))
+        Ok(McfList::new(self))
     }
 
-    /// Returns an error.
+    /// Tuple structs are written like structs, with each field in turn
+    /// separated by the delimiter, so `Pair(1, 2)` is written as `$1$2`.
     fn serialize_tuple_struct(self,
                               _name: &'static str,
                               _len: usize)
                               -> Result<Self::SerializeTupleStruct> {
-        Err(ErrorKind::Unsupported.into())
+        Ok(McfSeq(self, false))
     }
 
     fn serialize_tuple_variant(self,
@@ -151,7 +566,22 @@ impl<'a, W: Write> Serializer for &'a mut McfSerializer<W> {
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Ok(McfSeq(self, false))
+        let json = if self.options.json_params {
+            Some((serde_json::Map::new(), String::new()))
+        } else {
+            None
+        };
+        let sorted = if self.options.sort_map_keys && !self.options.json_params {
+            Some((Vec::new(), String::new()))
+        } else {
+            None
+        };
+        Ok(McfMap {
+            seq: McfSeq(self, false),
+            json,
+            flag: None,
+            sorted,
+        })
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
@@ -179,53 +609,111 @@ impl ser::Error for Error {
 }
 
 pub struct McfSeq<'a, W: 'a + Write>(&'a mut McfSerializer<W>, bool);
-impl<'a, W: Write> SerializeTuple for McfSeq<'a, W> {
-    type Ok = ();
-    type Error = Error;
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+/// Serializer for sequences and tuples.
+///
+/// Elements are written comma-separated. With the `base64_bytes` option, a
+/// non-empty sequence or tuple consisting only of `u8`s is written as
+/// unpadded base64 instead, so byte arrays can be serialized without
+/// `serde_bytes`.
+///
+/// Tuples nested in a sequence are flattened into it, so `vec![(1, 2), (3,
+/// 4)]` is written as `1,2,3,4`. Nested sequences are not supported, since
+/// their length would be unknown when reading them back.
+pub struct McfList<'a, W: 'a + Write> {
+    ser: &'a mut McfSerializer<W>,
+    // Leading `u8` elements, held back until we know if this is a byte array.
+    bytes: Vec<u8>,
+    // Whether this turned out not to be a byte array.
+    text: bool,
+    len: usize,
+}
+
+impl<'a, W: Write> McfList<'a, W> {
+    fn new(ser: &'a mut McfSerializer<W>) -> Self {
+        McfList {
+            text: !ser.options.base64_bytes,
+            ser,
+            bytes: Vec::new(),
+            len: 0,
+        }
+    }
+
+    fn element<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        if self.1 {
-            self.0.write(",")?;
+        if !self.text {
+            if let Ok(Probed::Byte(byte)) = value.serialize(ProbeSerializer) {
+                self.bytes.push(byte);
+                return Ok(());
+            }
+            // Not a byte array after all, so write out any bytes seen so far
+            // as regular elements.
+            self.text = true;
+            for byte in &self.bytes {
+                if self.len > 0 {
+                    self.ser.write_entry_separator()?;
+                }
+                self.len += 1;
+                self.ser.write(byte.to_string())?;
+            }
         }
-        self.1 = true;
-        self.0.write(value.serialize(StringSerializer)?)
+        if self.len > 0 {
+            self.ser.write_entry_separator()?;
+        }
+        self.len += 1;
+        value.serialize(StringSerializer::element(self.ser))
     }
 
-    fn end(self) -> Result<Self::Ok> {
+    fn finish(self) -> Result<()> {
+        if !self.text && !self.bytes.is_empty() {
+            super::base64::serialize(&self.bytes, &mut *self.ser)?;
+        }
         Ok(())
-
     }
 }
 
-impl<'a, W: Write> SerializeSeq for McfSeq<'a, W> {
+impl<'a, W: Write> SerializeTuple for McfList<'a, W> {
     type Ok = ();
     type Error = Error;
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        if self.1 {
-            self.0.write(",")?;
-        }
-        self.1 = true;
-        self.0.write(value.serialize(StringSerializer)?)
+        self.element(value)
     }
+
     fn end(self) -> Result<Self::Ok> {
-        Ok(())
+        self.finish()
+    }
+}
 
+impl<'a, W: Write> SerializeSeq for McfList<'a, W> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        self.finish()
     }
 }
 
 impl<'a, W: Write> SerializeStruct for McfSeq<'a, W> {
     type Ok = ();
     type Error = Error;
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
+        if self.0.options.skip_empty_params && is_empty_map(value) {
+            return Ok(());
+        }
         if self.1 {
-            self.0.write("$")?;
+            self.0.write_delimiter()?;
         }
         self.1 = true;
+        self.0.annotate_field(key)?;
         value.serialize(&mut *self.0)
     }
     fn end(self) -> Result<Self::Ok> {
@@ -233,14 +721,21 @@ impl<'a, W: Write> SerializeStruct for McfSeq<'a, W> {
     }
 }
 
-impl<'a, W: Write> SerializeStructVariant for &'a mut McfSerializer<W> {
+// Whether `value` serializes as a map with no entries. Structs are not maps,
+// even without fields.
+fn is_empty_map<T: ?Sized + Serialize>(value: &T) -> bool {
+    matches!(value.serialize(ProbeSerializer), Ok(Probed::Map(0)))
+}
+
+impl<W: Write> SerializeStructVariant for &mut McfSerializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        self.write("$")?;
+        self.write_delimiter()?;
+        self.annotate_field(key)?;
         value.serialize(&mut **self)
     }
 
@@ -249,14 +744,14 @@ impl<'a, W: Write> SerializeStructVariant for &'a mut McfSerializer<W> {
     }
 }
 
-impl<'a, W: Write> SerializeTupleVariant for &'a mut McfSerializer<W> {
+impl<W: Write> SerializeTupleVariant for &mut McfSerializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        self.write("$")?;
+        self.write_delimiter()?;
         value.serialize(&mut **self)
     }
 
@@ -265,14 +760,18 @@ impl<'a, W: Write> SerializeTupleVariant for &'a mut McfSerializer<W> {
     }
 }
 
-impl<'a, W: Write> SerializeTupleStruct for &'a mut McfSerializer<W> {
+impl<'a, W: Write> SerializeTupleStruct for McfSeq<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        self.write(value.serialize(StringSerializer)?)
+        if self.1 {
+            self.0.write_delimiter()?;
+        }
+        self.1 = true;
+        value.serialize(&mut *self.0)
     }
 
     fn end(self) -> Result<Self::Ok> {
@@ -284,176 +783,324 @@ impl<'a, W: Write> SerializeMap for McfSeq<'a, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
         if self.1 {
-            self.0.write(",")?;
+            self.0.write_entry_separator()?;
         }
         self.1 = true;
-        self.0.write(key.serialize(StringSerializer)?)?;
-        self.0.write("=")
+        key.serialize(StringSerializer::new(self.0))?;
+        self.0.write_key_separator()
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<()>
-        where T: Serialize
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
     {
-        self.0.write(value.serialize(StringSerializer)?)
+        write_map_value(self.0, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
         Ok(())
     }
 
-    fn serialize_entry<K: ?Sized, V: ?Sized>(&mut self, key: &K, value: &V) -> Result<()>
-        where K: Serialize,
-              V: Serialize
+    fn serialize_entry<K, V>(&mut self, key: &K, value: &V) -> Result<()>
+        where K: ?Sized + Serialize,
+              V: ?Sized + Serialize
     {
         if self.1 {
-            self.0.write(",")?;
+            self.0.write_entry_separator()?;
         }
-        self.0.write(key.serialize(StringSerializer)?)?;
-        self.0.write("=")?;
+        key.serialize(StringSerializer::new(self.0))?;
+        self.0.write_key_separator()?;
         self.1 = true;
-        self.0.write(value.serialize(StringSerializer)?)
+        write_map_value(self.0, value)
     }
 }
 
+// Write a map value, quoting it if it contains an entry separator and
+// `quote_values` is set. A value starting with `"` is quoted too, so it
+// reads back as written.
+fn write_map_value<T, W>(ser: &mut McfSerializer<W>, value: &T) -> Result<()>
+    where T: ?Sized + Serialize,
+          W: Write
+{
+    if !ser.options.quote_values {
+        return value.serialize(StringSerializer::value(ser));
+    }
+    let mut buf = Vec::new();
+    {
+        let mut rendered = McfSerializer::with_options(&mut buf, ser.options.clone());
+        value.serialize(StringSerializer::value(&mut rendered))?;
+    }
+    let entry = ser.options.dialect.entry;
+    if !String::from_utf8_lossy(&buf).contains(entry) && !buf.starts_with(b"\"") {
+        return ser.write(buf);
+    }
+    if buf.contains(&b'"') {
+        return Err(format!("cannot quote map value `{}`, which contains `\"`",
+                           String::from_utf8_lossy(&buf))
+            .into());
+    }
+    ser.write("\"")?;
+    ser.write(buf)?;
+    ser.write("\"")
+}
 
-#[cfg(test)]
-mod test {
-    use serde_bytes;
+/// Serializer for maps, which are either written as `key=value,...` or
+/// collected and written as a JSON object.
+pub struct McfMap<'a, W: 'a + Write> {
+    seq: McfSeq<'a, W>,
+    // The entries so far and the pending key, when writing JSON.
+    json: Option<(serde_json::Map<String, serde_json::Value>, String)>,
+    // The pending key when writing booleans as flags, which is only written
+    // once we know the value.
+    flag: Option<String>,
+    // The rendered entries so far and the pending key, when sorting by key.
+    sorted: Option<(Vec<RenderedEntry>, String)>,
+}
 
-    #[test]
-    fn test_serialize() {
-        #[derive(Serialize)]
-        struct TestStruct {
-            p: u8,
-            r: u8,
-            #[serde(with="serde_bytes")]
-            hash: [u8; 3],
-        }
+// A map key, and the whole entry as written.
+type RenderedEntry = (String, Vec<u8>);
 
-        let t = TestStruct {
-            p: 12,
-            r: 5,
-            hash: [0x12, 0x23, 0x34],
-        };
+// Serialize a map key to a `String`.
+fn key_to_string<T: ?Sized + Serialize>(key: &T) -> Result<String> {
+    let mut buf = Vec::new();
+    key.serialize(StringSerializer::new(&mut McfSerializer::new(&mut buf)))?;
+    Ok(String::from_utf8(buf).unwrap())
+}
 
-        let ts = super::to_string(&t).unwrap();
-        assert_eq!(ts, "$12$5$EiM0");
+impl<'a, W: Write> SerializeMap for McfMap<'a, W> {
+    type Ok = ();
+    type Error = Error;
 
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        if let Some((_, ref mut pending)) = self.sorted {
+            *pending = key_to_string(key)?;
+            return Ok(());
+        }
+        match self.json {
+            Some((_, ref mut pending)) => {
+                *pending = key_to_string(key)?;
+                Ok(())
+            }
+            None if self.seq.0.options.bools == BoolStyle::Flag => {
+                self.flag = Some(key_to_string(key)?);
+                Ok(())
+            }
+            None => self.seq.serialize_key(key),
+        }
+    }
 
-        #[derive(Serialize)]
-        #[serde(tag = "variant")]
-        enum TestEnum {
-            First { a: u8, b: u8 },
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        if let Some((ref mut entries, ref mut pending)) = self.sorted {
+            // Render the entry on its own, to be written in order by `end`.
+            let options = Options { sort_map_keys: false, ..self.seq.0.options.clone() };
+            let mut buf = Vec::new();
+            {
+                let mut ser = McfSerializer::with_options(&mut buf, options);
+                let mut map = (&mut ser).serialize_map(Some(1))?;
+                map.serialize_entry(pending.as_str(), value)?;
+                SerializeMap::end(map)?;
+            }
+            entries.push((::std::mem::take(pending), buf));
+            return Ok(());
+        }
+        match self.json {
+            Some((ref mut map, ref mut pending)) => {
+                let value = serde_json::to_value(value).map_err(<Error as ser::Error>::custom)?;
+                map.insert(::std::mem::take(pending), value);
+                Ok(())
+            }
+            None => {
+                if let Some(key) = self.flag.take() {
+                    match value.serialize(ProbeSerializer) {
+                        Ok(Probed::Bool(false)) => return Ok(()),
+                        Ok(Probed::Bool(true)) | Ok(Probed::Empty) => {
+                            if self.seq.1 {
+                                self.seq.0.write_entry_separator()?;
+                            }
+                            self.seq.1 = true;
+                            return self.seq.0.write(key);
+                        }
+                        _ => self.seq.serialize_key(&key)?,
+                    }
+                }
+                self.seq.serialize_value(value)
+            }
         }
+    }
 
-        let t = TestEnum::First { a: 38, b: 128 };
+    fn end(mut self) -> Result<Self::Ok> {
+        if let Some((mut entries, _)) = self.sorted.take() {
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            // Entries for `false` flags are empty.
+            for (_, entry) in entries.into_iter().filter(|e| !e.1.is_empty()) {
+                if self.seq.1 {
+                    self.seq.0.write_entry_separator()?;
+                }
+                self.seq.1 = true;
+                self.seq.0.write(entry)?;
+            }
+            return SerializeMap::end(self.seq);
+        }
+        match self.json {
+            Some((map, _)) => {
+                let json = if self.seq.0.options.sort_map_keys {
+                    let map = map.into_iter().collect::<BTreeMap<_, _>>();
+                    serde_json::to_string(&map)
+                } else {
+                    serde_json::to_string(&map)
+                };
+                self.seq.0.write(json.map_err(<Error as ser::Error>::custom)?)
+            }
+            None => SerializeMap::end(self.seq),
+        }
+    }
+}
 
-        let ts = super::to_string(&t).unwrap();
-        assert_eq!(ts, "$First$38$128");
+// Serializer which only accepts a `u8`, a `bool`, an empty string or a map,
+// used to detect byte arrays, boolean flags and empty maps.
+struct ProbeSerializer;
+
+// The value found by `ProbeSerializer`.
+enum Probed {
+    Byte(u8),
+    Bool(bool),
+    // An empty string, which is how `McfHash` reads a flag back.
+    Empty,
+    // A map, with its number of entries.
+    Map(usize),
+}
+
+// Counts the entries of a map for `ProbeSerializer`, without serializing them.
+struct ProbeMap(usize);
+
+impl SerializeMap for ProbeMap {
+    type Ok = Probed;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, _key: &T) -> Result<()> {
+        self.0 += 1;
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<()> {
+        Ok(())
+    }
+
+    fn end(self) -> Result<Probed> {
+        Ok(Probed::Map(self.0))
     }
 }
 
-struct StringSerializer;
+macro_rules! reject_as_unsupported {
+    ($($ty:ty => $meth:ident,)*) => {
+        $(
+            fn $meth(self, _v: $ty) -> Result<Self::Ok> {
+                Err(ErrorKind::Unsupported(&stringify!($meth)["serialize_".len()..]).into())
+            }
+        )*
+    };
+}
 
-impl Serializer for StringSerializer {
-    type Ok = String;
+impl Serializer for ProbeSerializer {
+    type Ok = Probed;
     type Error = Error;
-    type SerializeSeq = Impossible<String, Error>;
-    type SerializeTuple = Impossible<String, Error>;
-    type SerializeTupleStruct = Impossible<String, Error>;
-    type SerializeTupleVariant = Impossible<String, Error>;
-    type SerializeMap = Impossible<String, Error>;
-    type SerializeStruct = Impossible<String, Error>;
-    type SerializeStructVariant = Impossible<String, Error>;
+    type SerializeSeq = Impossible<Probed, Error>;
+    type SerializeTuple = Impossible<Probed, Error>;
+    type SerializeTupleStruct = Impossible<Probed, Error>;
+    type SerializeTupleVariant = Impossible<Probed, Error>;
+    type SerializeMap = ProbeMap;
+    type SerializeStruct = Impossible<Probed, Error>;
+    type SerializeStructVariant = Impossible<Probed, Error>;
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
+        Ok(Probed::Byte(v))
+    }
 
-    serialize_as_string!{
-        bool => serialize_bool,
-        u8  => serialize_u8,
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        Ok(Probed::Bool(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
+        if v.is_empty() {
+            Ok(Probed::Empty)
+        } else {
+            Err(ErrorKind::Unsupported("str").into())
+        }
+    }
+
+    reject_as_unsupported!{
         u16 => serialize_u16,
         u32 => serialize_u32,
         u64 => serialize_u64,
+        u128 => serialize_u128,
         i8  => serialize_i8,
         i16 => serialize_i16,
         i32 => serialize_i32,
         i64 => serialize_i64,
+        i128 => serialize_i128,
         f32 => serialize_f32,
         f64 => serialize_f64,
         char => serialize_char,
-        &str => serialize_str,
+        &[u8] => serialize_bytes,
+        &'static str => serialize_unit_struct,
     }
 
-
-    fn serialize_bytes(self, value: &[u8]) -> Result<Self::Ok> {
-        super::encoding::base64::serialize(&value, self)
-    }
-
-    /// Returns an error.
     fn serialize_unit(self) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
-    }
-
-    /// Returns an error.
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("unit").into())
     }
 
-    /// Returns an error.
     fn serialize_unit_variant(self,
                               _name: &'static str,
                               _variant_index: u32,
                               _variant: &'static str)
                               -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("unit_variant").into())
     }
 
-    /// Returns an error.
     fn serialize_newtype_struct<T: ?Sized + ser::Serialize>(self,
                                                             _name: &'static str,
                                                             _value: &T)
                                                             -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("newtype_struct").into())
     }
 
-    /// Returns an error.
     fn serialize_newtype_variant<T: ?Sized + ser::Serialize>(self,
                                                              _name: &'static str,
                                                              _variant_index: u32,
                                                              _variant: &'static str,
                                                              _value: &T)
                                                              -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("newtype_variant").into())
     }
 
-    /// Returns an error.
     fn serialize_none(self) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("none").into())
     }
 
-    /// Returns an error.
     fn serialize_some<T: ?Sized + ser::Serialize>(self, _value: &T) -> Result<Self::Ok> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("some").into())
     }
 
-    /// Returns an error.
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("seq").into())
     }
 
-
     fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("tuple").into())
     }
 
-    /// Returns an error.
     fn serialize_tuple_struct(self,
                               _name: &'static str,
                               _len: usize)
                               -> Result<Self::SerializeTupleStruct> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("tuple_struct").into())
     }
 
     fn serialize_tuple_variant(self,
@@ -462,16 +1109,15 @@ impl Serializer for StringSerializer {
                                _variant: &'static str,
                                _len: usize)
                                -> Result<Self::SerializeTupleVariant> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("tuple_variant").into())
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        Err(ErrorKind::Unsupported.into())
-
+        Ok(ProbeMap(0))
     }
 
     fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("struct").into())
     }
 
     fn serialize_struct_variant(self,
@@ -480,6 +1126,805 @@ impl Serializer for StringSerializer {
                                 _variant: &'static str,
                                 _len: usize)
                                 -> Result<Self::SerializeStructVariant> {
-        Err(ErrorKind::Unsupported.into())
+        Err(ErrorKind::Unsupported("struct_variant").into())
+    }
+}
+
+// Serializer for scalars inside lists and maps, which writes them straight to
+// the underlying writer rather than building an intermediate `String`.
+struct StringSerializer<'a, W: 'a + Write> {
+    ser: &'a mut McfSerializer<W>,
+    // Whether this is a map value, to which normalization applies.
+    value: bool,
+    // Whether this is a sequence element, into which nested tuples are
+    // flattened.
+    element: bool,
+}
+
+impl<'a, W: Write> StringSerializer<'a, W> {
+    fn new(ser: &'a mut McfSerializer<W>) -> Self {
+        StringSerializer {
+            ser,
+            value: false,
+            element: false,
+        }
+    }
+
+    fn value(ser: &'a mut McfSerializer<W>) -> Self {
+        StringSerializer { value: true, ..StringSerializer::new(ser) }
+    }
+
+    fn element(ser: &'a mut McfSerializer<W>) -> Self {
+        StringSerializer { element: true, ..StringSerializer::new(ser) }
+    }
+}
+
+impl<'a, W: Write> Serializer for StringSerializer<'a, W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = FlatTuple<'a, W>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = RawNumber<'a, W>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    serialize_as_string!{
+        u8  => serialize_u8,
+        u16 => serialize_u16,
+        u32 => serialize_u32,
+        u64 => serialize_u64,
+        u128 => serialize_u128,
+        i8  => serialize_i8,
+        i16 => serialize_i16,
+        i32 => serialize_i32,
+        i64 => serialize_i64,
+        i128 => serialize_i128,
+        char => serialize_char,
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
+        self.ser.write_float(v, v.is_finite())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
+        self.ser.write_float(v, v.is_finite())
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        self.ser.write_bool(v)
+    }
+
+    fn serialize_str(self, value: &str) -> Result<Self::Ok> {
+        if self.value {
+            self.ser.write_value(value)
+        } else {
+            self.ser.write(value)
+        }
+    }
+
+    fn serialize_bytes(self, value: &[u8]) -> Result<Self::Ok> {
+        super::encoding::base64::serialize(&value, self.ser)
+    }
+
+    /// Returns an error.
+    fn serialize_unit(self) -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("unit").into())
+    }
+
+    /// Returns an error.
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("unit_struct").into())
+    }
+
+    /// Returns an error.
+    fn serialize_unit_variant(self,
+                              _name: &'static str,
+                              _variant_index: u32,
+                              _variant: &'static str)
+                              -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("unit_variant").into())
+    }
+
+    /// Returns an error.
+    fn serialize_newtype_struct<T: ?Sized + ser::Serialize>(self,
+                                                            _name: &'static str,
+                                                            _value: &T)
+                                                            -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("newtype_struct").into())
+    }
+
+    /// Returns an error.
+    fn serialize_newtype_variant<T: ?Sized + ser::Serialize>(self,
+                                                             _name: &'static str,
+                                                             _variant_index: u32,
+                                                             _variant: &'static str,
+                                                             _value: &T)
+                                                             -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("newtype_variant").into())
+    }
+
+    /// Returns an error.
+    fn serialize_none(self) -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("none").into())
+    }
+
+    /// Returns an error.
+    fn serialize_some<T: ?Sized + ser::Serialize>(self, _value: &T) -> Result<Self::Ok> {
+        Err(ErrorKind::Unsupported("some").into())
+    }
+
+    /// Returns an error.
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(ErrorKind::Unsupported("seq").into())
+    }
+
+
+    /// Returns an error, other than for sequence elements, where the
+    /// tuple's elements are flattened into the sequence.
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        if self.element {
+            Ok(FlatTuple(self.ser, false))
+        } else {
+            Err(ErrorKind::Unsupported("tuple").into())
+        }
+    }
+
+    /// Returns an error.
+    fn serialize_tuple_struct(self,
+                              _name: &'static str,
+                              _len: usize)
+                              -> Result<Self::SerializeTupleStruct> {
+        Err(ErrorKind::Unsupported("tuple_struct").into())
+    }
+
+    fn serialize_tuple_variant(self,
+                               _name: &'static str,
+                               _variant_index: u32,
+                               _variant: &'static str,
+                               _len: usize)
+                               -> Result<Self::SerializeTupleVariant> {
+        Err(ErrorKind::Unsupported("tuple_variant").into())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(ErrorKind::Unsupported("map").into())
+
+    }
+
+    /// Returns an error, other than for the numbers of serde_json's
+    /// `arbitrary_precision` feature.
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        if name == SERDE_JSON_NUMBER {
+            Ok(RawNumber(self))
+        } else {
+            Err(ErrorKind::Unsupported("struct").into())
+        }
+    }
+
+    fn serialize_struct_variant(self,
+                                _name: &'static str,
+                                _variant_index: u32,
+                                _variant: &'static str,
+                                _len: usize)
+                                -> Result<Self::SerializeStructVariant> {
+        Err(ErrorKind::Unsupported("struct_variant").into())
+    }
+}
+
+// Writes the elements of a tuple nested in a sequence, comma-separated as
+// part of the enclosing sequence.
+struct FlatTuple<'a, W: 'a + Write>(&'a mut McfSerializer<W>, bool);
+
+impl<'a, W: Write> SerializeTuple for FlatTuple<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        if self.1 {
+            self.0.write_entry_separator()?;
+        }
+        self.1 = true;
+        value.serialize(StringSerializer::element(self.0))
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(())
+    }
+}
+
+// The name of the struct holding the exact text of a `serde_json::Number`,
+// with its `arbitrary_precision` feature enabled.
+const SERDE_JSON_NUMBER: &str = "$serde_json::private::Number";
+
+// Writes the text of a `serde_json::Number` with `arbitrary_precision`, which
+// is serialized as a struct with a single string field.
+struct RawNumber<'a, W: 'a + Write>(StringSerializer<'a, W>);
+
+impl<'a, W: Write> SerializeStruct for RawNumber<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, _key: &'static str, value: &T) -> Result<()>
+        where T: ?Sized + Serialize
+    {
+        value.serialize(StringSerializer {
+            ser: &mut *self.0.ser,
+            value: self.0.value,
+            element: false,
+        })
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_bytes;
+
+    #[test]
+    fn test_serialize() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            p: u8,
+            r: u8,
+            #[serde(with="serde_bytes")]
+            hash: [u8; 3],
+        }
+
+        let t = TestStruct {
+            p: 12,
+            r: 5,
+            hash: [0x12, 0x23, 0x34],
+        };
+
+        let ts = super::to_string(&t).unwrap();
+        assert_eq!(ts, "$12$5$EiM0");
+
+
+        #[derive(Serialize)]
+        #[serde(tag = "variant")]
+        enum TestEnum {
+            First { a: u8, b: u8 },
+        }
+
+        let t = TestEnum::First { a: 38, b: 128 };
+
+        let ts = super::to_string(&t).unwrap();
+        assert_eq!(ts, "$First$38$128");
+    }
+
+    #[test]
+    fn test_serialize_byte_arrays() {
+        use super::Options;
+
+        #[derive(Serialize)]
+        struct TestStruct {
+            p: u8,
+            hash: [u8; 3],
+            salt: Vec<u8>,
+            list: Vec<u16>,
+        }
+
+        let t = TestStruct {
+            p: 12,
+            hash: [0x12, 0x23, 0x34],
+            salt: vec![0x12, 0x23, 0x34],
+            list: vec![1, 2],
+        };
+        let options = Options { base64_bytes: true, ..Default::default() };
+        assert_eq!(super::to_string_with_options(&t, options.clone()).unwrap(),
+                   "$12$EiM0$EiM0$1,2");
+        assert_eq!(super::to_string(&t).unwrap(), "$12$18,35,52$18,35,52$1,2");
+
+        // A tuple is only a byte array if every element is a `u8`.
+        assert_eq!(super::to_string_with_options(&(1u8, 2u16), options).unwrap(), "$1,2");
+    }
+
+    #[test]
+    fn test_byte_tuples() {
+        use from_str;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            pairs: Vec<(u8, u8)>,
+            pair: (u8, u8),
+        }
+
+        let t = TestStruct {
+            pairs: vec![(1, 2), (3, 4)],
+            pair: (5, 6),
+        };
+        let encoded = super::to_string(&t).unwrap();
+        assert_eq!(encoded, "$1,2,3,4$5,6");
+        assert_eq!(from_str::<TestStruct>(&encoded).unwrap(), t);
+
+        let encoded = super::to_string(&(5u8, 6u8)).unwrap();
+        assert_eq!(encoded, "$5,6");
+        assert_eq!(from_str::<(u8, u8)>(&encoded).unwrap(), (5, 6));
+    }
+
+    #[test]
+    fn test_writer_line() {
+        use std::io::BufRead;
+        use {from_str, McfHash};
+
+        let hashes = ["$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ$aGFzaA",
+                      "$argon2i$m=4096,p=2,t=3$c2FsdA$aGFzaGhhc2g"];
+        let options = super::Options {
+            terminator: Some('\n'),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        for hash in &hashes {
+            let hash: McfHash = from_str(hash).unwrap();
+            super::to_writer_with_options(&mut buf, &hash, options.clone()).unwrap();
+        }
+        assert!(buf.ends_with(b"\n"));
+
+        let lines = buf.lines().collect::<::std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(lines, hashes);
+        for line in &lines {
+            assert!(from_str::<McfHash>(line).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_mcf_writer() {
+        use std::io::BufRead;
+        use from_str;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Record {
+            id: u32,
+            #[serde(with = "serde_bytes")]
+            hash: Vec<u8>,
+        }
+
+        let records = (0..100)
+            .map(|id| {
+                Record {
+                    id,
+                    hash: vec![id as u8; 16],
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut writer = super::McfWriter::new(Vec::new());
+        for record in &records {
+            writer.write(record).unwrap();
+        }
+        writer.flush().unwrap();
+        let buf = writer.into_inner().unwrap();
+
+        let lines = buf.lines().collect::<::std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(lines.len(), 100);
+        assert_eq!(lines[1], "$1$AQEBAQEBAQEBAQEBAQEBAQ");
+        for (line, record) in lines.iter().zip(&records) {
+            assert_eq!(&from_str::<Record>(line).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn test_serialize_entries() {
+        use super::McfSerializer;
+        use {from_str, McfHash};
+
+        let hashes = ["$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ$aGFzaA",
+                      "$argon2i$m=4096,p=2,t=3$c2FsdA$aGFzaGhhc2g",
+                      "$argon2d$m=1024,p=1,t=1$c2FsdA$aGFzaA"];
+        let mut ser = McfSerializer::new(Vec::new());
+        for hash in &hashes {
+            ser.serialize_entry(&from_str::<McfHash>(hash).unwrap()).unwrap();
+        }
+        let out = String::from_utf8(ser.into_inner()).unwrap();
+        assert_eq!(out, hashes.join("\n") + "\n");
+
+        let mut ser = McfSerializer::new(Vec::new()).separator(";");
+        ser.serialize_entry(&from_str::<McfHash>(hashes[0]).unwrap()).unwrap();
+        assert_eq!(ser.into_inner(), (hashes[0].to_string() + ";").into_bytes());
+    }
+
+    #[test]
+    fn test_annotated() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            p: u8,
+            r: u8,
+            #[serde(with="serde_bytes")]
+            hash: [u8; 3],
+        }
+
+        let t = TestStruct {
+            p: 12,
+            r: 5,
+            hash: [0x12, 0x23, 0x34],
+        };
+        assert_eq!(super::to_annotated_string(&t).unwrap(), "$[p]12$[r]5$[hash]EiM0");
+    }
+
+    #[test]
+    fn test_serialize_empty_bytes() {
+        #[derive(Serialize)]
+        struct TestStruct {
+            p: u8,
+            #[serde(with="serde_bytes")]
+            salt: Vec<u8>,
+            #[serde(with="::base64")]
+            hash: Vec<u8>,
+        }
+
+        let t = TestStruct {
+            p: 12,
+            salt: vec![],
+            hash: vec![],
+        };
+        assert_eq!(super::to_string(&t).unwrap(), "$12$$");
+    }
+
+    #[test]
+    fn test_leading_zeros() {
+        use {from_str, McfHash, Value};
+
+        let digest = "lQ8jolhgVRVhY4b5pZKaysCLi0QBxGoNeKQzQ3glMhwllF7oGDZxUhx1yxdYcz/\
+                      e1JSbq3y6JMxxl8audkUEm0";
+        let input = format!("$6$rounds=05000$toolongsaltstrin${}", digest);
+        let hash: McfHash = from_str(&input).unwrap();
+        assert_eq!(hash.parameters["rounds"], Value::String("05000".to_string()));
+        assert_eq!(super::to_string(&hash).unwrap(), input);
+
+        let options = super::Options {
+            normalize_numbers: true,
+            ..Default::default()
+        };
+        assert_eq!(super::to_string_with_options(&hash, options).unwrap(),
+                   format!("$6$rounds=5000$toolongsaltstrin${}", digest));
+    }
+
+    #[test]
+    fn test_terminator() {
+        use {from_str, McfHash};
+
+        let input = "$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA";
+        let hash: McfHash = from_str(input).unwrap();
+        let options = super::Options {
+            terminator: Some('\n'),
+            ..Default::default()
+        };
+        let line = super::to_string_with_options(&hash, options.clone()).unwrap();
+        assert_eq!(line, format!("{}\n", input));
+        assert_eq!(super::to_string(&hash).unwrap(), input);
+
+        let mut buf = Vec::new();
+        super::to_writer_with_options(&mut buf, &hash, options.clone()).unwrap();
+        assert_eq!(buf, line.as_bytes());
+        buf.clear();
+        super::to_writer(&mut buf, &hash).unwrap();
+        assert_eq!(buf, input.as_bytes());
+
+        let mut ser = super::McfSerializer::with_options(Vec::new(), options).separator(";");
+        ser.serialize_entry(&hash).unwrap();
+        assert_eq!(ser.into_inner(), format!("{}\n;", input).into_bytes());
+
+        let options = super::Options {
+            terminator: Some('\0'),
+            ..Default::default()
+        };
+        let mut writer = super::McfWriter::with_options(Vec::new(), options);
+        writer.write(&hash).unwrap();
+        assert_eq!(writer.into_inner().unwrap(), format!("{}\0", input).into_bytes());
+    }
+
+    #[test]
+    fn test_json_params() {
+        use {from_str, McfHash, Value};
+
+        let input = "$argon2id${\"m\":65536,\"t\":3}$c29tZXNhbHQ$aGFzaA";
+        let hash: McfHash = from_str(input).unwrap();
+        assert_eq!(hash.parameters["m"], Value::Number(65536.into()));
+        assert_eq!(hash.parameters["t"], Value::Number(3.into()));
+        assert_eq!(hash.salt, Some(b"somesalt".to_vec()));
+
+        let options = super::Options {
+            json_params: true,
+            ..Default::default()
+        };
+        assert_eq!(super::to_string_with_options(&hash, options).unwrap(), input);
+        assert_eq!(super::to_string(&hash).unwrap(), "$argon2id$m=65536,t=3$c29tZXNhbHQ$aGFzaA");
+    }
+
+    #[test]
+    fn test_sort_map_keys() {
+        use {from_str, McfHash};
+        use super::{BoolStyle, Options};
+
+        let hash: McfHash = from_str("$custom$b=2,a=1,c=0$c2FsdA$aGFzaA").unwrap();
+        assert_eq!(super::to_string(&hash).unwrap(), "$custom$b=2,a=1,c=0$c2FsdA$aGFzaA");
+
+        let options = Options { sort_map_keys: true, ..Default::default() };
+        assert_eq!(super::to_string_with_options(&hash, options).unwrap(),
+                   "$custom$a=1,b=2,c=0$c2FsdA$aGFzaA");
+
+        let options = Options { sort_map_keys: true, json_params: true, ..Default::default() };
+        assert_eq!(super::to_string_with_options(&hash, options).unwrap(),
+                   "$custom${\"a\":\"1\",\"b\":\"2\",\"c\":\"0\"}$c2FsdA$aGFzaA");
+
+        let mut flags = ::Map::new();
+        flags.insert("z".to_string(), true.into());
+        flags.insert("y".to_string(), false.into());
+        flags.insert("x".to_string(), true.into());
+        let options = Options {
+            sort_map_keys: true,
+            bools: BoolStyle::Flag,
+            ..Default::default()
+        };
+        assert_eq!(super::to_string_with_options(&flags, options).unwrap(), "$x,z");
+    }
+
+    #[test]
+    fn test_sort_hash_map() {
+        use std::collections::HashMap;
+        use super::Options;
+
+        let options = Options { sort_map_keys: true, ..Default::default() };
+        let outputs = (0..10)
+            .map(|_| {
+                // Each map has its own random hasher, so iteration order varies.
+                let params = (0..20)
+                    .map(|i| (format!("k{:02}", i), i.to_string()))
+                    .collect::<HashMap<_, _>>();
+                super::to_string_with_options(&params, options.clone()).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let expected = (0..20).map(|i| format!("k{:02}={}", i, i)).collect::<Vec<_>>().join(",");
+        for output in outputs {
+            assert_eq!(output, format!("${}", expected));
+        }
+    }
+
+    #[test]
+    fn test_skip_empty_params() {
+        use {Hashes, McfHash};
+        use super::Options;
+
+        let mut hash = McfHash {
+            algorithm: Hashes::BsdNtHash,
+            parameters: ::Map::new(),
+            salt: Some(b"salt".to_vec()),
+            hash: b"hash".to_vec(),
+        };
+        assert_eq!(super::to_string(&hash).unwrap(), "$3$$c2FsdA$aGFzaA");
+
+        let options = Options { skip_empty_params: true, ..Default::default() };
+        assert_eq!(super::to_string_with_options(&hash, options.clone()).unwrap(),
+                   "$3$c2FsdA$aGFzaA");
+
+        hash.parameters.insert("x".to_string(), 1.into());
+        assert_eq!(super::to_string_with_options(&hash, options.clone()).unwrap(),
+                   "$3$x=1$c2FsdA$aGFzaA");
+
+        // Only maps are left out, not structs without fields.
+        #[derive(Serialize)]
+        struct Empty {}
+        #[derive(Serialize)]
+        struct Outer {
+            a: u8,
+            inner: Empty,
+            map: ::std::collections::BTreeMap<String, u8>,
+            b: u8,
+        }
+        let outer = Outer {
+            a: 1,
+            inner: Empty {},
+            map: Default::default(),
+            b: 2,
+        };
+        assert_eq!(super::to_string(&outer).unwrap(), "$1$$$2");
+        assert_eq!(super::to_string_with_options(&outer, options).unwrap(), "$1$$2");
+    }
+
+    #[test]
+    fn test_bool_styles() {
+        use std::collections::BTreeMap;
+        use from_str;
+        use super::{BoolStyle, Options};
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Flags {
+            a: bool,
+            b: BTreeMap<String, bool>,
+        }
+
+        let mut b = BTreeMap::new();
+        b.insert("x".to_string(), false);
+        b.insert("y".to_string(), true);
+        let flags = Flags { a: true, b };
+        let with_style = |bools| {
+            let options = Options { bools, ..Default::default() };
+            super::to_string_with_options(&flags, options).unwrap()
+        };
+
+        let words = with_style(BoolStyle::Words);
+        assert_eq!(words, "$true$x=false,y=true");
+        assert_eq!(super::to_string(&flags).unwrap(), words);
+        assert_eq!(from_str::<Flags>(&words).unwrap(), flags);
+
+        let numeric = with_style(BoolStyle::Numeric);
+        assert_eq!(numeric, "$1$x=0,y=1");
+        assert_eq!(from_str::<Flags>(&numeric).unwrap(), flags);
+
+        // False flags are omitted, so only the true ones come back.
+        let flag = with_style(BoolStyle::Flag);
+        assert_eq!(flag, "$true$y");
+        let parsed = from_str::<Flags>(&flag).unwrap();
+        assert_eq!(parsed.b.into_iter().collect::<Vec<_>>(), [("y".to_string(), true)]);
+
+        assert_eq!(from_str::<Flags>("$true$x=0,y=true").unwrap(), flags);
+        assert!(from_str::<Flags>("$2$x=0").is_err());
+    }
+
+    #[test]
+    fn test_flag_params() {
+        use {from_str, McfHash, Value};
+        use super::{BoolStyle, Options};
+
+        let mut hash: McfHash = from_str("$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA").unwrap();
+        hash.parameters.insert("fips".to_string(), Value::Bool(true));
+        hash.parameters.insert("legacy".to_string(), Value::Bool(false));
+        let options = Options {
+            bools: BoolStyle::Flag,
+            ..Default::default()
+        };
+        let flagged = super::to_string_with_options(&hash, options.clone()).unwrap();
+        assert_eq!(flagged, "$argon2i$m=1,t=1,p=1,fips$c2FsdA$aGFzaA");
+
+        let parsed: McfHash = from_str(&flagged).unwrap();
+        assert_eq!(parsed.param_bool("fips"), Some(true));
+        assert_eq!(parsed.param_bool("legacy"), None);
+        assert_eq!(hash.param_bool("fips"), Some(true));
+        assert_eq!(hash.param_bool("legacy"), Some(false));
+        assert_eq!(parsed.param_bool("m"), Some(true));
+        assert_eq!(super::to_string_with_options(&parsed, options).unwrap(), flagged);
+    }
+
+    #[test]
+    fn test_delimiter() {
+        use de::McfDeserializer;
+        use serde::Deserialize;
+        use {from_str, McfHash};
+
+        let hash: McfHash = from_str("$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA").unwrap();
+        let options = super::Options {
+            dialect: ::Dialect { field: ':', ..::Dialect::mcf() },
+            ..Default::default()
+        };
+        let encoded = super::to_string_with_options(&hash, options).unwrap();
+        assert_eq!(encoded, ":argon2i:m=1,t=1,p=1:c2FsdA:aGFzaA");
+
+        let mut de = McfDeserializer::with_delimiter(&encoded, ':');
+        assert_eq!(McfHash::deserialize(&mut de).unwrap(), hash);
+
+        #[allow(deprecated)]
+        let options = super::Options {
+            delimiter: ':',
+            ..Default::default()
+        };
+        assert_eq!(super::to_string_with_options(&hash, options).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_floats() {
+        use std::collections::BTreeMap;
+        use from_str;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            factor: f64,
+            params: BTreeMap<String, f64>,
+        }
+
+        let mut params = BTreeMap::new();
+        params.insert("big".to_string(), 1e20);
+        params.insert("small".to_string(), 1.5e-7);
+        let value = TestStruct {
+            factor: 0.75,
+            params,
+        };
+        let encoded = super::to_string(&value).unwrap();
+        assert_eq!(encoded, "$0.75$big=100000000000000000000,small=0.00000015");
+        assert_eq!(from_str::<TestStruct>(&encoded).unwrap(), value);
+
+        let infinite = TestStruct {
+            factor: f64::INFINITY,
+            params: BTreeMap::new(),
+        };
+        assert!(super::to_string(&infinite).is_err());
+        let mut params = BTreeMap::new();
+        params.insert("nan".to_string(), f64::NAN);
+        assert!(super::to_string(&TestStruct { factor: 1.0, params }).is_err());
+    }
+
+    #[test]
+    fn test_128_bit() {
+        use std::collections::BTreeMap;
+        use from_str;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            memory: u128,
+            params: BTreeMap<String, i128>,
+        }
+
+        let mut params = BTreeMap::new();
+        params.insert("offset".to_string(), i128::MIN);
+        let value = TestStruct {
+            memory: u128::from(u64::MAX) + 1,
+            params,
+        };
+        let encoded = super::to_string(&value).unwrap();
+        assert_eq!(encoded,
+                   "$18446744073709551616$offset=-170141183460469231731687303715884105728");
+        assert_eq!(from_str::<TestStruct>(&encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn test_string_in() {
+        use arrayvec::ArrayString;
+        use {from_str, McfHash};
+
+        let argon_hash = "$argon2i$m=262144,p=1,t=2$c29tZXNhbHQ\
+                          $Pmiaqj0op3zyvHKlGsUxZnYXURgvHuKS4/Z3p9pMJGc";
+        let hash: McfHash = from_str(argon_hash).unwrap();
+
+        let mut buf = ArrayString::<128>::new();
+        super::to_string_in(&mut buf, &hash).unwrap();
+        assert_eq!(buf.as_str(), argon_hash);
+
+        let mut small = ArrayString::<32>::new();
+        assert!(super::to_string_in(&mut small, &hash).is_err());
+    }
+
+    #[test]
+    fn test_unsupported() {
+        #[derive(Serialize)]
+        struct Unit;
+
+        #[derive(Serialize)]
+        struct Pair(u8, u8);
+
+        let err = super::to_string(&Unit).unwrap_err();
+        assert_eq!(err.to_string(), "serializing a `unit_struct` is not supported");
+        // Tuple structs are only supported as whole fields, not map values.
+        let mut map = ::std::collections::BTreeMap::new();
+        map.insert("pair", Pair(1, 2));
+        let err = super::to_string(&map).unwrap_err();
+        assert!(err.to_string().contains("`tuple_struct`"));
+    }
+
+    #[test]
+    fn test_tuple_struct() {
+        use from_str;
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct SaltHash(String, #[serde(with = "::serde_bytes")] Vec<u8>);
+
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct TestStruct {
+            cost: u8,
+            salthash: SaltHash,
+        }
+
+        let value = TestStruct {
+            cost: 10,
+            salthash: SaltHash("salt".to_string(), b"hash".to_vec()),
+        };
+        let encoded = super::to_string(&value).unwrap();
+        assert_eq!(encoded, "$10$salt$aGFzaA");
+        assert_eq!(from_str::<TestStruct>(&encoded).unwrap(), value);
+        assert_eq!(super::to_string(&value.salthash).unwrap(), "$salt$aGFzaA");
     }
 }
@@ -0,0 +1,92 @@
+//! Reading the password hashes from an `/etc/shadow`-style file.
+//!
+//! Each line has the form `user:hash:...`, with the hash in the second
+//! column. Accounts without a usable hash have a password field such as `*`
+//! or `!`, or a hash prefixed with `!` when the account is locked.
+
+use errors::*;
+use legacy::from_crypt_str;
+use prelude::*;
+use super::McfHash;
+
+// Parse one line, returning `None` for lines without a hash to parse.
+fn shadow_entry(line: &str) -> Option<Result<(String, McfHash)>> {
+    let line = line.trim_end_matches('\r');
+    if line.trim().is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut columns = line.split(':');
+    let user = columns.next().unwrap();
+    let password = match columns.next() {
+        Some(password) => password,
+        None => return Some(Err(format!("no password field for user `{}`", user).into())),
+    };
+    if password.is_empty() || password.starts_with('*') || password.starts_with('!') {
+        return None;
+    }
+    Some(from_crypt_str(password)
+        .map(|hash| (user.to_string(), hash))
+        .map_err(|e| format!("invalid hash for user `{}`: {}", user, e).into()))
+}
+
+/// Parse a single line of a shadow file into the username and hash.
+///
+/// Returns `None` for blank lines, `#` comments, accounts whose password
+/// field is empty, `*` or starts with `!`, and lines which fail to parse.
+/// Use `parse_shadow` to see the errors.
+pub fn parse_shadow_line(line: &str) -> Option<(String, McfHash)> {
+    shadow_entry(line).and_then(|entry| entry.ok())
+}
+
+/// Parse every hash in a shadow file.
+///
+/// Lines which `parse_shadow_line` would skip are left out, other than those
+/// which fail to parse, which give an error.
+pub fn parse_shadow(input: &str) -> impl Iterator<Item = Result<(String, McfHash)>> + '_ {
+    input.lines().filter_map(shadow_entry)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Hashes;
+
+    const SHADOW: &str = "\
+# Synthetic shadow file
+root:$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/:19000:0:99999:7:::
+daemon:*:19000:0:99999:7:::
+alice:$y$j9T$F5Jx5fExrKuPp53xLKQ..1$tnSYvahCwPBHKZUspmcxMfb0.WiB9W.zEaKlOBL35rC:19000:0:99999:7:::
+locked:!$6$saltsalt$qFmFH.bQmmtXzyBY0s9v7Oicd2z4XSIecDzlB5KiA2/jctKu9YterLp8wwnSq.qc.eoxqOmSuNp2xS0ktL3nh/:19000::::::
+nopass::19000:0:99999:7:::
+
+bob:!:19000:0:99999:7:::
+";
+
+    #[test]
+    fn test_parse_shadow() {
+        let entries = parse_shadow(SHADOW).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let (ref user, ref hash) = entries[0];
+        assert_eq!(user, "root");
+        assert_eq!(hash.algorithm, Hashes::Sha512Crypt);
+        assert_eq!(hash.salt, Some(b"saltsalt".to_vec()));
+        assert_eq!(hash.hash.len(), 64);
+
+        let (ref user, ref hash) = entries[1];
+        assert_eq!(user, "alice");
+        assert_eq!(hash.algorithm, Hashes::Yescrypt);
+        assert!(hash.parameters.contains_key("j9T"));
+        assert_eq!(hash.salt, Some(b"F5Jx5fExrKuPp53xLKQ..1".to_vec()));
+        assert_eq!(hash.hash.len(), 32);
+        let column = SHADOW.lines().nth(3).unwrap().split(':').nth(1).unwrap();
+        assert_eq!(::to_string(hash).unwrap(), column);
+
+        let bad = "carol:$6$salt$tooshort:19000:0:99999:7:::";
+        assert!(parse_shadow(bad).next().unwrap().is_err());
+        assert!(parse_shadow_line(bad).is_none());
+        assert!(parse_shadow("nofields").next().unwrap().is_err());
+        assert_eq!(parse_shadow_line(SHADOW.lines().nth(3).unwrap()).unwrap(),
+                   entries[1].clone());
+    }
+}
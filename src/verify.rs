@@ -0,0 +1,102 @@
+//! Dispatching password verification to user-supplied implementations.
+
+use std::collections::BTreeMap;
+
+use errors::*;
+use prelude::*;
+use super::{from_str, Hashes, McfHash};
+
+type Verifier = Box<dyn Fn(&McfHash, &[u8]) -> bool + Send + Sync>;
+
+/// A set of verification functions, keyed by algorithm.
+///
+/// This crate does not implement any hash algorithms itself, so callers
+/// register a verifier for each algorithm they support, and `verify` picks
+/// the right one for a parsed hash.
+#[derive(Default)]
+pub struct VerifierRegistry {
+    // Keyed by the algorithm identifier, since `Hashes` is not `Ord`.
+    verifiers: BTreeMap<String, Verifier>,
+}
+
+impl VerifierRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        VerifierRegistry::default()
+    }
+
+    /// Register the verifier for `algorithm`, replacing any existing one.
+    ///
+    /// The verifier is given the parsed hash and the candidate password, and
+    /// returns whether they match.
+    pub fn register<F>(&mut self, algorithm: Hashes, verifier: F)
+        where F: Fn(&McfHash, &[u8]) -> bool + Send + Sync + 'static
+    {
+        self.verifiers.insert(algorithm.to_id().to_string(), Box::new(verifier));
+    }
+
+    /// Verify `password` against `hash` using the verifier registered for
+    /// its algorithm.
+    ///
+    /// Returns an error if no verifier is registered for the algorithm.
+    pub fn verify(&self, hash: &McfHash, password: &[u8]) -> Result<bool> {
+        match self.verifiers.get(hash.algorithm.to_id()) {
+            Some(verifier) => Ok(verifier(hash, password)),
+            None => Err(ErrorKind::UnsupportedAlgorithm(hash.algorithm.to_id().to_string()).into()),
+        }
+    }
+}
+
+/// Check a digest computed by the caller against the hash in `stored`.
+///
+/// This is for callers who run the key derivation themselves, using the
+/// parameters and salt of the parsed hash, and only need the result
+/// compared. The comparison takes the same time wherever the digests
+/// differ, though a digest of the wrong length is rejected straight away.
+/// Returns an error if `stored` does not parse.
+pub fn verify(stored: &str, candidate_digest: &[u8]) -> Result<bool> {
+    let hash: McfHash = from_str(stored)?;
+    Ok(constant_time_eq(hash.digest(), candidate_digest))
+}
+
+// Compare two byte strings without stopping at the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    ::std::hint::black_box(diff) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut registry = VerifierRegistry::new();
+        // A "hash" which is just the password itself.
+        registry.register(Hashes::Custom, |hash, password| hash.hash == password);
+
+        let hash: McfHash = from_str("$custom$x=1$c2FsdA$aHVudGVyMg").unwrap();
+        assert!(registry.verify(&hash, b"hunter2").unwrap());
+        assert!(!registry.verify(&hash, b"hunter3").unwrap());
+
+        let hash: McfHash = from_str("$argon2i$m=1,t=1,p=1$c2FsdA$aGFzaA").unwrap();
+        assert!(registry.verify(&hash, b"hunter2").is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let stored = "$argon2i$m=65536,t=2,p=1$c29tZXNhbHQ$aGFzaA";
+        assert!(verify(stored, b"hash").unwrap());
+        assert!(!verify(stored, b"hasH").unwrap());
+        assert!(!verify(stored, b"hash!").unwrap());
+        assert!(!verify(stored, b"").unwrap());
+
+        let sha = "$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5";
+        let digest = from_str::<McfHash>(sha).unwrap().digest().to_vec();
+        assert!(verify(sha, &digest).unwrap());
+        assert!(verify("argon2i$m=1$c2FsdA$aGFzaA", b"hash").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+// Counts the allocations made while serializing. This runs without the test
+// harness, as its own binary, so the counting allocator is not installed for
+// the unit tests and no other threads allocate while counting.
+extern crate serde_mcf;
+
+use serde_mcf::{from_str, to_writer, McfHash};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+fn allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+// Scalars in the parameters are written straight to the writer, so the number
+// of allocations does not grow with the parameters.
+fn param_allocations() {
+    let params = (0..200).map(|i| format!("p{}={}", i, i)).collect::<Vec<_>>().join(",");
+    let hash: McfHash = from_str(&format!("$argon2i${}$c29tZXNhbHQ$aGFzaA", params)).unwrap();
+    let one: McfHash = from_str("$argon2i$p0=0$c29tZXNhbHQ$aGFzaA").unwrap();
+    let mut buf = Vec::with_capacity(4096);
+    let mut count = |hash: &McfHash| {
+        buf.clear();
+        allocations(|| to_writer(&mut buf, hash).unwrap())
+    };
+    assert_eq!(count(&hash), count(&one));
+}
+
+fn main() {
+    param_allocations();
+    println!("test param_allocations ... ok");
+}